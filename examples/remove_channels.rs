@@ -2,12 +2,10 @@
 //use actix_rt::Runtime;
 // use actor_discord::discord::ExampleDiscordActor;
 use actor_discord::types::events::ChannelType;
-use actor_discord::DiscordAPI;
+use actor_discord::{DiscordAPI, DiscordConfig};
 //use actor_discord::DiscordBot;
-use actor_discord::GatewayIntents;
 use anyhow::Result;
 use dotenv::dotenv;
-use std::env;
 use std::env::args;
 
 #[actix_rt::main]
@@ -17,21 +15,12 @@ async fn main() -> Result<()> {
     log::info!("Starting");
 
     let channel_name = args().nth(1).expect("Please provide a channel name");
-    let token = env::var("DISCORD_TOKEN")?;
-    let guild_id = env::var("DISCORD_GUILD_ID")?;
-
-    let url = env::var("DISCORD_URL")?;
-    let retries: usize = env::var("DISCORD_RETRIES").unwrap_or("4".into()).parse()?;
-    let intents: GatewayIntents = GatewayIntents::GUILDS
-            // | GatewayIntents::GUILD_MESSAGE_TYPING
-            | GatewayIntents::DIRECT_MESSAGES
-            | GatewayIntents::GUILD_MESSAGES
-            | GatewayIntents::GUILD_MESSAGE_REACTIONS
-            | GatewayIntents::DIRECT_MESSAGE_REACTIONS;
-    log::info!("** Intents = {}", intents.bits);
+    let config = DiscordConfig::from_env()?;
+    let guild_id = config.guild_id.expect("Please set DISCORD_GUILD_ID");
+    log::info!("** Intents = {}", config.intents);
 
     log::info!("attempting to create websocket");
-    let discord_api = DiscordAPI::create(&token, &url, retries)?;
+    let discord_api = DiscordAPI::from_config(&config)?;
     // let mut connect = DiscordBot::create(&discord_api, intents).await?;
     log::info!("attempting to create actor");
 
@@ -41,21 +30,25 @@ async fn main() -> Result<()> {
     //let _actor_addr = actor.start();
     log::info!("creating threads");
 
-    let channels = discord_api.channels(guild_id.as_str().into()).await?;
-    let foo = channels
+    let channels = discord_api.channels(guild_id).await?;
+    let matching = channels
         .iter()
         .filter(|c| {
             c.parent_id.is_none() && c.u_type == ChannelType::GuildText && c.name == channel_name
         })
         .collect::<Vec<_>>();
-    let bb = futures::future::join_all(foo.iter().map(|c| discord_api.delete_channel(c.id))).await;
+    let bb =
+        futures::future::join_all(matching.iter().map(|c| discord_api.delete_channel(c.id))).await;
     bb.iter().for_each(|cr| match cr {
         Ok(gc) => {
-            log::info!("{}", gc.id.to_string())
-        }
-        Err(e) => {
-            log::error!("{}", e)
+            log::info!("{}", gc.id)
         }
+        Err(e) => match e.api_error() {
+            Some(api_error) if api_error.is_unknown_channel() => {
+                log::info!("already deleted")
+            }
+            _ => log::error!("{}", e),
+        },
     });
 
     log::info!("{}", channels.len());
@@ -1,8 +1,7 @@
 use actor_discord::types::events::ChannelType;
-use actor_discord::DiscordAPI;
+use actor_discord::{DiscordAPI, DiscordConfig};
 use anyhow::Result;
 use dotenv::dotenv;
-use std::env;
 
 #[actix_rt::main]
 async fn main() -> Result<()> {
@@ -10,15 +9,13 @@ async fn main() -> Result<()> {
     env_logger::init();
     log::info!("Starting");
 
-    let token = env::var("DISCORD_TOKEN")?;
-    let guild_id = env::var("DISCORD_GUILD_ID")?;
-    let url = env::var("DISCORD_URL")?;
-    let retries: usize = env::var("DISCORD_RETRIES").unwrap_or("4".into()).parse()?;
+    let config = DiscordConfig::from_env()?;
+    let guild_id = config.guild_id.expect("Please set DISCORD_GUILD_ID");
 
-    let discord_api = DiscordAPI::create(&token, &url, retries)?;
+    let discord_api = DiscordAPI::from_config(&config)?;
 
-    let channels = discord_api.channels(guild_id.as_str().into()).await?;
-    let mut foo = channels
+    let channels = discord_api.channels(guild_id).await?;
+    let mut sorted = channels
         .iter()
         .filter(|c| {
             if let Some(topic) = &c.topic {
@@ -31,11 +28,11 @@ async fn main() -> Result<()> {
         })
         .collect::<Vec<_>>();
 
-    foo.sort_by(|a, b| b.name.cmp(&a.name));
+    sorted.sort_by(|a, b| b.name.cmp(&a.name));
 
     log::info!("#Channels Total: {}", channels.len());
     let mut i = channels.len();
-    for channel in foo {
+    for channel in sorted {
         log::info!("{} {}", i, channel.name);
 
         if i != channel.position {
@@ -46,7 +43,7 @@ async fn main() -> Result<()> {
                 )
                 .await?;
         }
-        i = i - 1;
+        i -= 1;
     }
 
     log::info!("done");
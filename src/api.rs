@@ -1,51 +1,104 @@
-use anyhow::Result;
 use awc::http::StatusCode;
 //use awc::{ws, Client, ClientBuilder};
 use awc::{Client, ClientResponse};
 use lazy_static::lazy_static;
 //use futures_util::{sink::SinkExt as _, stream::StreamExt as _};
-use crate::errors::ActorDiscordError;
+use crate::errors::{ActorDiscordError, DiscordApiError, Result};
+use crate::ratelimit::{RateLimitHeaders, RateLimiter, Route};
 use crate::types::events::{
     Guild, GuildChannel, GuildChannelCreate, MessageCreate, MessageObject, RetryMessage,
     SnowflakeID,
 };
+use crate::DiscordConfig;
 use actix_http::encoding::Decoder;
 use actix_http::Payload;
 use regex::Regex;
 use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::Duration;
 use url::Url;
 
-const API_PREFIX: &str = "/api/v9/";
-
 const GUILD_ID: &str = "guilds/";
+
+/// (timeout, connect timeout)
+type Timeouts = (Option<Duration>, Option<Duration>);
+
+thread_local! {
+    /// awc clients can't leave the thread they were built on, so each thread builds its own
+    static CLIENTS: RefCell<HashMap<Timeouts, Client>> = RefCell::new(HashMap::new());
+}
+
+/// The REST API. It is `Send` and `Sync`, share it through an `Arc`: the HTTP client is built
+/// on each thread that uses it
 pub struct DiscordAPI {
-    pub client: Client,
     pub base_url: Url,
     pub token: String,
     pub max_retries: usize,
+    /// REST API and gateway version
+    pub version: u8,
+    /// to wait for a response
+    pub timeout: Option<Duration>,
+    /// to wait for a connection to open
+    pub connect_timeout: Option<Duration>,
+    rate_limiter: RateLimiter,
 }
 impl DiscordAPI {
     pub fn create(token: &str, connect_addr: &str, max_retries: usize) -> Result<DiscordAPI> {
-        let base_url: Url = Url::from_str(connect_addr)?.join(API_PREFIX)?;
+        DiscordAPI::from_config(&DiscordConfig {
+            token: token.into(),
+            url: connect_addr.into(),
+            retries: max_retries,
+            ..Default::default()
+        })
+    }
 
-        let client = Client::builder().finish();
+    pub fn from_config(config: &DiscordConfig) -> Result<DiscordAPI> {
+        let base_url: Url =
+            Url::from_str(&config.url)?.join(&format!("/api/v{}/", config.version))?;
         Ok(DiscordAPI {
-            client,
             base_url,
-            token: token.into(),
-            max_retries,
+            token: config.token.clone(),
+            max_retries: config.retries,
+            version: config.version,
+            timeout: config.timeout(),
+            connect_timeout: config.connect_timeout(),
+            rate_limiter: Default::default(),
+        })
+    }
+
+    /// the HTTP client for the current thread
+    pub fn client(&self) -> Client {
+        CLIENTS.with(|clients| {
+            clients
+                .borrow_mut()
+                .entry((self.timeout, self.connect_timeout))
+                .or_insert_with(|| {
+                    let mut connector = awc::Connector::new();
+                    if let Some(timeout) = self.connect_timeout {
+                        connector = connector.timeout(timeout);
+                    }
+                    let mut client = Client::builder().connector(connector);
+                    if let Some(timeout) = self.timeout {
+                        client = client.timeout(timeout);
+                    }
+                    client.finish()
+                })
+                .clone()
         })
     }
 
-    pub async fn get<T: for<'de> Deserialize<'de>>(&self, url_suffix: &str) -> anyhow::Result<T> {
+    pub async fn get<T: for<'de> Deserialize<'de>>(&self, url_suffix: &str) -> Result<T> {
         let full_url = self.base_url.join(url_suffix)?;
+        let route = Route::new("GET", full_url.path());
 
         let mut retries = self.max_retries;
         while retries > 0 {
+            let _discovering = self.rate_limiter.acquire(&route).await;
             log::debug!("Get URL={}", full_url.as_str());
             let response = self
-                .client
+                .client()
                 .get(full_url.as_str())
                 .insert_header((awc::http::header::CONTENT_TYPE, "application/json"))
                 .insert_header((awc::http::header::USER_AGENT, "PFC-Discord"))
@@ -55,35 +108,39 @@ impl DiscordAPI {
                 ))
                 .send()
                 .await
-                .map_err(|source| {
-                    eprintln!("{:#?}", source);
-                    ActorDiscordError::ResponseErrorMsg {
-                        url: full_url.to_string(),
-                        err: source.to_string(),
-                    }
+                .map_err(|source| ActorDiscordError::Http {
+                    url: full_url.to_string(),
+                    err: source.to_string(),
                 })?;
-            let ok_retryable = self.handle_response::<T>(response).await?;
+            self.rate_limiter
+                .update(&route, &RateLimitHeaders::from_headers(response.headers()));
+            let ok_retryable = self.handle_response::<T>(&full_url, response).await?;
             if ok_retryable.0 {
                 return Ok(ok_retryable.1.unwrap());
             }
             log::debug!("Retrying retries left:{}", retries);
             retries -= 1;
         }
-        Err(ActorDiscordError::RetryError.into())
+        Err(ActorDiscordError::RateLimited {
+            url: full_url.to_string(),
+            retries: self.max_retries,
+        })
     }
     pub async fn post<T: for<'de> Deserialize<'de>>(
         &self,
         url_suffix: &str,
         args: serde_json::Value,
-    ) -> anyhow::Result<T> {
+    ) -> Result<T> {
         let full_url = self.base_url.join(url_suffix)?;
+        let route = Route::new("POST", full_url.path());
 
         let mut retries = self.max_retries;
         while retries > 0 {
+            let _discovering = self.rate_limiter.acquire(&route).await;
             log::debug!("Post URL={}", full_url.as_str());
-            let arg_json = serde_json::to_string(&args)?;
+            let arg_json = serde_json::to_string(&args).map_err(ActorDiscordError::encode)?;
             let response = self
-                .client
+                .client()
                 .post(full_url.as_str())
                 .insert_header((awc::http::header::CONTENT_TYPE, "application/json"))
                 .insert_header((awc::http::header::USER_AGENT, "PFC-Discord"))
@@ -93,34 +150,35 @@ impl DiscordAPI {
                 ))
                 .send_body(arg_json)
                 .await
-                .map_err(|source| {
-                    eprintln!("{:#?}", source);
-                    ActorDiscordError::ResponseErrorMsg {
-                        url: full_url.to_string(),
-                        err: source.to_string(),
-                    }
+                .map_err(|source| ActorDiscordError::Http {
+                    url: full_url.to_string(),
+                    err: source.to_string(),
                 })?;
-            let ok_retryable = self.handle_response::<T>(response).await?;
+            self.rate_limiter
+                .update(&route, &RateLimitHeaders::from_headers(response.headers()));
+            let ok_retryable = self.handle_response::<T>(&full_url, response).await?;
             if ok_retryable.0 {
                 return Ok(ok_retryable.1.unwrap());
             }
             log::debug!("Retrying retries left:{}", retries);
             retries -= 1;
         }
-        Err(ActorDiscordError::RetryError.into())
+        Err(ActorDiscordError::RateLimited {
+            url: full_url.to_string(),
+            retries: self.max_retries,
+        })
     }
-    pub async fn delete<T: for<'de> Deserialize<'de>>(
-        &self,
-        url_suffix: &str,
-    ) -> anyhow::Result<T> {
+    pub async fn delete<T: for<'de> Deserialize<'de>>(&self, url_suffix: &str) -> Result<T> {
         let full_url = self.base_url.join(url_suffix)?;
+        let route = Route::new("DELETE", full_url.path());
 
         let mut retries = self.max_retries;
         while retries > 0 {
+            let _discovering = self.rate_limiter.acquire(&route).await;
             log::debug!("Delete URL={}", full_url.as_str());
 
             let response = self
-                .client
+                .client()
                 .delete(full_url.as_str())
                 .insert_header((awc::http::header::CONTENT_TYPE, "application/json"))
                 .insert_header((awc::http::header::USER_AGENT, "PFC-Discord"))
@@ -130,35 +188,39 @@ impl DiscordAPI {
                 ))
                 .send()
                 .await
-                .map_err(|source| {
-                    eprintln!("{:#?}", source);
-                    ActorDiscordError::ResponseErrorMsg {
-                        url: full_url.to_string(),
-                        err: source.to_string(),
-                    }
+                .map_err(|source| ActorDiscordError::Http {
+                    url: full_url.to_string(),
+                    err: source.to_string(),
                 })?;
-            let ok_retryable = self.handle_response::<T>(response).await?;
+            self.rate_limiter
+                .update(&route, &RateLimitHeaders::from_headers(response.headers()));
+            let ok_retryable = self.handle_response::<T>(&full_url, response).await?;
             if ok_retryable.0 {
                 return Ok(ok_retryable.1.unwrap());
             }
             log::debug!("Retrying retries left:{}", retries);
             retries -= 1;
         }
-        Err(ActorDiscordError::RetryError.into())
+        Err(ActorDiscordError::RateLimited {
+            url: full_url.to_string(),
+            retries: self.max_retries,
+        })
     }
     pub async fn patch<T: for<'de> Deserialize<'de>>(
         &self,
         url_suffix: &str,
         args: serde_json::Value,
-    ) -> anyhow::Result<T> {
+    ) -> Result<T> {
         let full_url = self.base_url.join(url_suffix)?;
+        let route = Route::new("PATCH", full_url.path());
 
         let mut retries = self.max_retries;
         while retries > 0 {
+            let _discovering = self.rate_limiter.acquire(&route).await;
             log::debug!("Patch URL={}", full_url.as_str());
-            let arg_json = serde_json::to_string(&args)?;
+            let arg_json = serde_json::to_string(&args).map_err(ActorDiscordError::encode)?;
             let response = self
-                .client
+                .client()
                 .patch(full_url.as_str())
                 .insert_header((awc::http::header::CONTENT_TYPE, "application/json"))
                 .insert_header((awc::http::header::USER_AGENT, "PFC-Discord"))
@@ -168,21 +230,23 @@ impl DiscordAPI {
                 ))
                 .send_body(arg_json)
                 .await
-                .map_err(|source| {
-                    eprintln!("{:#?}", source);
-                    ActorDiscordError::ResponseErrorMsg {
-                        url: full_url.to_string(),
-                        err: source.to_string(),
-                    }
+                .map_err(|source| ActorDiscordError::Http {
+                    url: full_url.to_string(),
+                    err: source.to_string(),
                 })?;
-            let ok_retryable = self.handle_response::<T>(response).await?;
+            self.rate_limiter
+                .update(&route, &RateLimitHeaders::from_headers(response.headers()));
+            let ok_retryable = self.handle_response::<T>(&full_url, response).await?;
             if ok_retryable.0 {
                 return Ok(ok_retryable.1.unwrap());
             }
             log::debug!("Retrying retries left:{}", retries);
             retries -= 1;
         }
-        Err(ActorDiscordError::RetryError.into())
+        Err(ActorDiscordError::RateLimited {
+            url: full_url.to_string(),
+            retries: self.max_retries,
+        })
     }
 
     /**
@@ -192,14 +256,28 @@ impl DiscordAPI {
     */
     async fn handle_response<T: for<'de> Deserialize<'de>>(
         &self,
+        url: &Url,
         mut response: ClientResponse<Decoder<Payload>>,
     ) -> Result<(bool, Option<T>)> {
+        let body =
+            response
+                .body()
+                .limit(1024 * 1024)
+                .await
+                .map_err(|err| ActorDiscordError::Http {
+                    url: url.to_string(),
+                    err: err.to_string(),
+                })?;
+        let decode = |err| ActorDiscordError::decode(url.as_str(), err, &body);
         if response.status() == StatusCode::CREATED || response.status() == StatusCode::OK {
-            let result: T = response.json::<T>().limit(1024 * 1024).await?;
+            let result: T = serde_json::from_slice(&body).map_err(decode)?;
             return Ok((true, Some(result)));
         }
         if response.status() == StatusCode::TOO_MANY_REQUESTS {
-            let retry: RetryMessage = response.json::<RetryMessage>().await?;
+            let retry: RetryMessage = serde_json::from_slice(&body).map_err(decode)?;
+            if retry.global {
+                self.rate_limiter.set_global(retry.retry_after);
+            }
             log::debug!(
                 "Sleeping for {} seconds :{}",
                 retry.retry_after,
@@ -208,12 +286,9 @@ impl DiscordAPI {
             tokio::time::sleep(tokio::time::Duration::from_secs_f64(retry.retry_after)).await;
             return Ok((false, None));
         }
-        log::error!(
-            "{} {}",
-            response.status(),
-            std::str::from_utf8(&response.body().limit(6000).await.unwrap())?
-        );
-        Err(ActorDiscordError::ResponseError().into())
+        let error = DiscordApiError::from_body(response.status().as_u16(), &body);
+        log::error!("{}", error);
+        Err(error.into())
     }
     pub async fn guild(&self, id: SnowflakeID) -> Result<Guild> {
         let url = self.base_url.join(GUILD_ID)?.join(&id.to_string())?;
@@ -221,7 +296,7 @@ impl DiscordAPI {
         Ok(guild)
     }
     pub async fn channels(&self, guild_id: SnowflakeID) -> Result<Vec<GuildChannel>> {
-        let prefix = format!("{}{}/channels", GUILD_ID, guild_id.to_string());
+        let prefix = format!("{}{}/channels", GUILD_ID, guild_id);
         let url = self.base_url.join(&prefix)?;
         let channels: Vec<GuildChannel> = self.get(url.as_str()).await?;
         Ok(channels)
@@ -231,13 +306,13 @@ impl DiscordAPI {
         guild_id: SnowflakeID,
         channel_details: GuildChannelCreate,
     ) -> Result<GuildChannel> {
-        let prefix = format!("{}{}/channels", GUILD_ID, guild_id.to_string());
+        let prefix = format!("{}{}/channels", GUILD_ID, guild_id);
         //   let url = self.base_url.join(&prefix)?;
-        self.post(&prefix, serde_json::to_value(&channel_details)?)
-            .await
+        let args = serde_json::to_value(&channel_details).map_err(ActorDiscordError::encode)?;
+        self.post(&prefix, args).await
     }
     pub async fn delete_channel(&self, channel_id: SnowflakeID) -> Result<GuildChannel> {
-        let prefix = format!("channels/{}", channel_id.to_string());
+        let prefix = format!("channels/{}", channel_id);
         //   let url = self.base_url.join(&prefix)?;
         self.delete(&prefix).await
     }
@@ -246,7 +321,7 @@ impl DiscordAPI {
         channel_id: SnowflakeID,
         args: serde_json::Value,
     ) -> Result<GuildChannel> {
-        let prefix = format!("channels/{}", channel_id.to_string());
+        let prefix = format!("channels/{}", channel_id);
         //   let url = self.base_url.join(&prefix)?;
         self.patch(&prefix, args).await
     }
@@ -272,15 +347,134 @@ impl DiscordAPI {
         channel_id: SnowflakeID,
         message: MessageCreate,
     ) -> Result<MessageObject> {
-        let prefix = format!("channels/{}/messages", channel_id.to_string());
+        let prefix = format!("channels/{}/messages", channel_id);
         //   let url = self.base_url.join(&prefix)?;
-        let args = serde_json::to_value(message)?;
+        let args = serde_json::to_value(message).map_err(ActorDiscordError::encode)?;
         self.post(&prefix, args).await
     }
 }
 #[cfg(test)]
-mod tests {
-    use crate::DiscordAPI;
+pub(crate) mod tests {
+    use crate::{ActorDiscordError, DiscordAPI, DiscordApiError, FieldError};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::time::{Duration, Instant};
+
+    /// minimal HTTP server returning an exhausted bucket on every response
+    pub(crate) async fn mock_server(
+        requests: Arc<Mutex<Vec<Instant>>>,
+        status: &'static str,
+        body: &'static str,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await.unwrap();
+                requests.lock().unwrap().push(Instant::now());
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                     X-RateLimit-Bucket: abcd\r\nX-RateLimit-Limit: 1\r\nX-RateLimit-Remaining: 0\r\n\
+                     X-RateLimit-Reset-After: 0.3\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                let _ = socket.shutdown().await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[actix_rt::test]
+    async fn waits_for_bucket() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = mock_server(
+            requests.clone(),
+            "200 OK",
+            r#"{"id":"1","name":"test","owner_id":"2"}"#,
+        )
+        .await;
+        let api = DiscordAPI::create("token", &url, 1).unwrap();
+        api.guild(1.into()).await.unwrap();
+        api.guild(1.into()).await.unwrap();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1] - requests[0] >= Duration::from_millis(250));
+    }
+
+    #[actix_rt::test]
+    async fn first_requests_wait_for_bucket() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = mock_server(
+            requests.clone(),
+            "200 OK",
+            r#"{"id":"1","name":"test","owner_id":"2"}"#,
+        )
+        .await;
+        let api = DiscordAPI::create("token", &url, 1).unwrap();
+        // the bucket isn't known yet, so only one may go before the headers arrive
+        let results = futures::future::join_all((0..3).map(|_| api.guild(1.into()))).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[1] - requests[0] >= Duration::from_millis(250));
+        assert!(requests[2] - requests[1] >= Duration::from_millis(250));
+    }
+
+    #[actix_rt::test]
+    async fn api_errors() {
+        let url = mock_server(
+            Default::default(),
+            "404 Not Found",
+            r#"{"message":"Unknown Channel","code":10003}"#,
+        )
+        .await;
+        let api = DiscordAPI::create("token", &url, 1).unwrap();
+        let err = api.delete_channel(1.into()).await.unwrap_err();
+        let err = err.api_error().unwrap();
+        assert!(err.is_unknown_channel());
+        assert!(err.is_not_found());
+        assert!(!err.is_missing_permissions());
+        assert_eq!(err.message, "Unknown Channel");
+
+        let err = DiscordApiError::from_body(
+            400,
+            br#"{"code":50035,"message":"Invalid Form Body","errors":{"name":{"_errors":[{"code":"BASE_TYPE_REQUIRED","message":"This field is required"}]},"embeds":{"0":{"title":{"_errors":[{"code":"BASE_TYPE_MAX_LENGTH","message":"Must be 256 or fewer in length."}]}}}}}"#,
+        );
+        assert_eq!(err.code, DiscordApiError::INVALID_FORM_BODY);
+        let mut field_errors = err.field_errors();
+        field_errors.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            field_errors,
+            vec![
+                FieldError {
+                    path: "embeds.0.title".into(),
+                    code: "BASE_TYPE_MAX_LENGTH".into(),
+                    message: "Must be 256 or fewer in length.".into(),
+                },
+                FieldError {
+                    path: "name".into(),
+                    code: "BASE_TYPE_REQUIRED".into(),
+                    message: "This field is required".into(),
+                },
+            ]
+        );
+        let url = mock_server(Default::default(), "200 OK", r#"{"id":"1"}"#).await;
+        let api = DiscordAPI::create("token", &url, 1).unwrap();
+        match api.guild(1.into()).await.unwrap_err() {
+            ActorDiscordError::Decode { body, .. } => assert_eq!(body, r#"{"id":"1"}"#),
+            err => panic!("expected a decode error {:?}", err),
+        }
+
+        let err = DiscordApiError::from_body(502, b"<html>Bad Gateway</html>");
+        assert_eq!((err.status, err.code), (502, 0));
+        assert_eq!(err.message, "<html>Bad Gateway</html>");
+    }
 
     #[test]
     fn sanitize() {
@@ -0,0 +1,65 @@
+use rand::Rng;
+use std::time::Duration;
+
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+/// stop doubling after this many attempts. 2^16 seconds is well past the max delay anyway
+const MAX_EXPONENT: u32 = 16;
+
+/// Exponential backoff with jitter, used between gateway reconnect attempts.
+/// each delay is picked randomly between half and all of the exponential delay, so a fleet of
+/// bots doesn't reconnect in lockstep after an outage.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    attempt: u32,
+}
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Backoff {
+        Backoff {
+            base,
+            max,
+            attempt: 0,
+        }
+    }
+    /// the delay before the next attempt
+    pub fn next_delay(&mut self) -> Duration {
+        let exponential = self
+            .base
+            .saturating_mul(1 << self.attempt.min(MAX_EXPONENT))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        let half = exponential / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+    /// call once a connection has been established successfully
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(BASE_DELAY, MAX_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backoff;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let first = backoff.next_delay();
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+        let second = backoff.next_delay();
+        assert!(second >= Duration::from_secs(1) && second <= Duration::from_secs(2));
+        for _ in 0..40 {
+            assert!(backoff.next_delay() <= Duration::from_secs(10));
+        }
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::connection::{GatewayConnection, GatewayHandle, ShutdownMode};
+use crate::errors::Result;
+use crate::types::events::{ConnectionState, ConnectionStatus, EventGroup, GatewayLatency};
+use crate::types::gateway::{GatewayCommand, GatewaySession, Shard};
+use crate::{BrokerType, DiscordAPI, DiscordConfig, GatewayIntents};
+use actix::dev::ToEnvelope;
+use actix::{
+    Actor, ActorFutureExt, AsyncContext, Context, Handler, Message, MessageResponse, SpawnHandle,
+    WrapFuture,
+};
+use actix_broker::BrokerSubscribe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// dispatches of the groups subscribed to with [SubscribeEvents]
+static SUBSCRIBED_EVENTS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// the dispatches subscribed to with [SubscribeEvents] so far
+pub fn subscribed_events() -> Vec<&'static str> {
+    SUBSCRIBED_EVENTS.lock().unwrap().clone()
+}
+
+/// Subscribes an actor to an [EventGroup] on the broker, and records its dispatches so each connection
+/// warns about the ones its intents won't deliver once it is ready
+/// ```ignore
+/// fn started(&mut self, ctx: &mut Self::Context) {
+///     self.subscribe_events::<MessageEvent>(ctx);
+/// }
+/// ```
+pub trait SubscribeEvents: BrokerSubscribe
+where
+    <Self as Actor>::Context: AsyncContext<Self>,
+{
+    fn subscribe_events<E: EventGroup>(&self, ctx: &mut Self::Context)
+    where
+        Self: Handler<E>,
+        <Self as Actor>::Context: ToEnvelope<Self, E>,
+    {
+        self.subscribe_sync::<BrokerType, E>(ctx);
+        let mut subscribed = SUBSCRIBED_EVENTS.lock().unwrap();
+        for event in E::EVENTS {
+            if !subscribed.contains(event) {
+                subscribed.push(event);
+            }
+        }
+    }
+}
+impl<A> SubscribeEvents for A
+where
+    A: Actor,
+    <A as Actor>::Context: AsyncContext<A>,
+{
+}
+
+/// start the gateway connection. does nothing if it is already running
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Start;
+
+/// send the queued commands and close the connection. does nothing if it isn't running
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Stop(pub ShutdownMode);
+
+#[derive(Debug, Message)]
+#[rtype(result = "BotStatus")]
+pub struct GetStatus;
+
+#[derive(Debug, Clone, MessageResponse)]
+pub struct BotStatus {
+    pub shard: Shard,
+    pub state: ConnectionState,
+    /// the connection loop is running, it may be between connections
+    pub running: bool,
+    pub latency: Option<Duration>,
+    /// average over the last few heartbeats
+    pub average_latency: Option<Duration>,
+    /// the session a restarted process can RESUME, once stopped with [ShutdownMode::KeepSession]
+    pub session: Option<GatewaySession>,
+}
+
+/// queue a command, it is sent once the connection is ready
+#[derive(Debug, Message)]
+#[rtype(result = "Result<()>")]
+pub struct SendCommand(pub GatewayCommand);
+
+/// Runs a [GatewayConnection] as an actor.
+/// ```no_run
+/// # async fn run(api: std::sync::Arc<actor_discord::DiscordAPI>) -> anyhow::Result<()> {
+/// use actix::Actor;
+/// use actor_discord::bot::{DiscordBot, Start};
+/// let bot = DiscordBot::create(api, actor_discord::GatewayIntents::GUILDS).await?.start();
+/// bot.send(Start).await?;
+/// # Ok(())
+/// # }
+/// ```
+/// The bot is `Send`, so it can be moved onto another arbiter, e.g. with
+/// `DiscordBot::start_in_arbiter(&arbiter.handle(), move |_| bot)`
+pub struct DiscordBot {
+    pub shard: Shard,
+    handle: GatewayHandle,
+    /// the connection, while it isn't running
+    connection: Option<GatewayConnection>,
+    running: Option<SpawnHandle>,
+    state: ConnectionState,
+    latency: Option<Duration>,
+    average_latency: Option<Duration>,
+}
+impl DiscordBot {
+    /// a bot for an unsharded connection
+    pub async fn create(api: Arc<DiscordAPI>, intents: GatewayIntents) -> Result<DiscordBot> {
+        Ok(DiscordBot::new(
+            GatewayConnection::create(api, intents).await?,
+        ))
+    }
+
+    /// a bot for the configured intents, and shard if one is set
+    pub async fn from_config(config: &DiscordConfig) -> Result<DiscordBot> {
+        let api = Arc::new(DiscordAPI::from_config(config)?);
+        let mut connection = GatewayConnection::create(api, config.intents).await?;
+        if let Some(shard) = config.shard() {
+            connection.shard = shard;
+        }
+        Ok(DiscordBot::new(connection))
+    }
+
+    pub fn new(connection: GatewayConnection) -> DiscordBot {
+        DiscordBot {
+            shard: connection.shard,
+            handle: connection.handle(),
+            state: connection.state,
+            connection: Some(connection),
+            running: None,
+            latency: None,
+            average_latency: None,
+        }
+    }
+
+    /// a handle other actors can use to send commands without going through this actor
+    pub fn handle(&self) -> GatewayHandle {
+        self.handle.clone()
+    }
+
+    /// the subscribed and expected dispatches the intents won't deliver, with a warning logged for each.
+    /// empty while the connection is running, it checks them itself once ready
+    pub fn check_events(&self) -> Vec<&'static str> {
+        self.connection
+            .as_ref()
+            .map(GatewayConnection::check_events)
+            .unwrap_or_default()
+    }
+}
+impl Actor for DiscordBot {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.subscribe_sync::<BrokerType, ConnectionStatus>(ctx);
+        self.subscribe_sync::<BrokerType, GatewayLatency>(ctx);
+    }
+}
+impl Handler<Start> for DiscordBot {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Start, ctx: &mut Self::Context) {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let running = async move {
+            let result = connection.start_websocket().await;
+            (connection, result)
+        }
+        .into_actor(self)
+        .map(|(connection, result), bot, _ctx| {
+            if let Err(e) = result {
+                log::error!("Shard {} stopped {}", bot.shard, e);
+            }
+            bot.connection = Some(connection);
+            bot.running = None;
+        });
+        self.running = Some(ctx.spawn(running));
+    }
+}
+impl Handler<Stop> for DiscordBot {
+    type Result = ();
+
+    fn handle(&mut self, msg: Stop, _ctx: &mut Self::Context) {
+        // a shutdown requested while stopped would end the next run straight away
+        if self.running.is_some() {
+            if let Err(e) = self.handle.shutdown(msg.0) {
+                log::error!("Shard {} unable to stop {}", self.shard, e);
+            }
+        }
+    }
+}
+impl Handler<GetStatus> for DiscordBot {
+    type Result = BotStatus;
+
+    fn handle(&mut self, _msg: GetStatus, _ctx: &mut Self::Context) -> BotStatus {
+        BotStatus {
+            shard: self.shard,
+            state: self.state,
+            running: self.running.is_some(),
+            latency: self.latency,
+            average_latency: self.average_latency,
+            session: self
+                .connection
+                .as_ref()
+                .and_then(GatewayConnection::session),
+        }
+    }
+}
+impl Handler<SendCommand> for DiscordBot {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: SendCommand, _ctx: &mut Self::Context) -> Result<()> {
+        self.handle.send(msg.0)
+    }
+}
+impl Handler<ConnectionStatus> for DiscordBot {
+    type Result = ();
+
+    fn handle(&mut self, msg: ConnectionStatus, _ctx: &mut Self::Context) {
+        if msg.shard == self.shard {
+            self.state = msg.state;
+        }
+    }
+}
+impl Handler<GatewayLatency> for DiscordBot {
+    type Result = ();
+
+    fn handle(&mut self, msg: GatewayLatency, _ctx: &mut Self::Context) {
+        if msg.shard == self.shard {
+            self.latency = Some(msg.latest);
+            self.average_latency = Some(msg.average);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiscordBot, GetStatus, SendCommand, Stop, SubscribeEvents};
+    use crate::api::tests::mock_server;
+    use crate::connection::ShutdownMode;
+    use crate::types::events::ConnectionState;
+    use crate::types::events::{GatewayLatency, MemberEvent, Status};
+    use crate::types::gateway::{GatewayCommand, UpdatePresence};
+    use crate::{DiscordAPI, GatewayConnection, GatewayIntents};
+    use actix::{Actor, Arbiter, Context, Handler};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use url::Url;
+
+    fn test_bot() -> DiscordBot {
+        let api = DiscordAPI::create("token", "https://discord.com", 1).unwrap();
+        let connection = GatewayConnection::new(
+            Arc::new(api),
+            GatewayIntents::empty(),
+            Url::from_str("wss://gateway.discord.gg").unwrap(),
+            Default::default(),
+            None,
+        );
+        DiscordBot::new(connection)
+    }
+
+    #[actix_rt::test]
+    async fn status() {
+        let bot = test_bot().start();
+
+        let status = bot.send(GetStatus).await.unwrap();
+        assert_eq!(status.state, ConnectionState::Disconnected);
+        assert!(!status.running);
+        assert!(status.latency.is_none());
+        assert!(status.average_latency.is_none());
+        assert!(status.session.is_none());
+
+        // commands queue until the connection is started and ready
+        bot.send(SendCommand(GatewayCommand::PresenceUpdate(
+            UpdatePresence::new(Status::Idle),
+        )))
+        .await
+        .unwrap()
+        .unwrap();
+        // stopping a bot that isn't running leaves it startable
+        bot.send(Stop(ShutdownMode::EndSession)).await.unwrap();
+        assert!(!bot.send(GetStatus).await.unwrap().running);
+
+        bot.send(GatewayLatency {
+            shard: Default::default(),
+            latest: Duration::from_millis(40),
+            average: Duration::from_millis(55),
+        })
+        .await
+        .unwrap();
+        let status = bot.send(GetStatus).await.unwrap();
+        assert_eq!(status.latency, Some(Duration::from_millis(40)));
+        assert_eq!(status.average_latency, Some(Duration::from_millis(55)));
+    }
+
+    #[actix_rt::test]
+    async fn other_arbiter() {
+        fn is_send<T: Send>() {}
+        is_send::<DiscordBot>();
+        is_send::<Arc<DiscordAPI>>();
+
+        let arbiter = Arbiter::new();
+        let bot = test_bot();
+        let bot = DiscordBot::start_in_arbiter(&arbiter.handle(), move |_| bot);
+        let status = bot.send(GetStatus).await.unwrap();
+        assert_eq!(status.state, ConnectionState::Disconnected);
+        assert!(!status.running);
+        arbiter.stop();
+    }
+
+    struct Members;
+    impl Actor for Members {
+        type Context = Context<Self>;
+        fn started(&mut self, ctx: &mut Self::Context) {
+            self.subscribe_events::<MemberEvent>(ctx);
+        }
+    }
+    impl Handler<MemberEvent> for Members {
+        type Result = ();
+        fn handle(&mut self, _msg: MemberEvent, _ctx: &mut Self::Context) {}
+    }
+
+    #[actix_rt::test]
+    async fn subscribed_events() {
+        let url = mock_server(
+            Default::default(),
+            "200 OK",
+            r#"{"url":"wss://gateway.discord.gg"}"#,
+        )
+        .await;
+        let api = Arc::new(DiscordAPI::create("token", &url, 1).unwrap());
+        let bot = DiscordBot::create(api, GatewayIntents::GUILDS)
+            .await
+            .unwrap();
+
+        Members.start();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // member events need GUILD_MEMBERS, nothing had to be listed by hand
+        let missing = bot.check_events();
+        assert!(missing.contains(&"GUILD_MEMBER_ADD"));
+        assert!(missing.contains(&"GUILD_MEMBER_REMOVE"));
+    }
+}
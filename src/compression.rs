@@ -0,0 +1,157 @@
+#[cfg(feature = "zlib-stream")]
+use crate::errors::{ActorDiscordError, Result};
+use actix_http::ws::Item;
+#[cfg(feature = "zlib-stream")]
+use flate2::{Decompress, FlushDecompress, Status};
+
+/// every complete zlib-stream message ends with a Z_SYNC_FLUSH
+#[cfg(feature = "zlib-stream")]
+const ZLIB_SUFFIX: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Reassembles fragmented websocket frames
+#[derive(Debug, Default)]
+pub struct Fragments {
+    binary: bool,
+    buffer: Vec<u8>,
+}
+impl Fragments {
+    /// returns (is_binary, payload) once the last fragment arrives
+    pub fn push(&mut self, item: Item) -> Option<(bool, Vec<u8>)> {
+        match item {
+            Item::FirstText(data) => {
+                self.binary = false;
+                self.buffer = data.to_vec();
+            }
+            Item::FirstBinary(data) => {
+                self.binary = true;
+                self.buffer = data.to_vec();
+            }
+            Item::Continue(data) => self.buffer.extend_from_slice(&data),
+            Item::Last(data) => {
+                self.buffer.extend_from_slice(&data);
+                return Some((self.binary, std::mem::take(&mut self.buffer)));
+            }
+        }
+        None
+    }
+}
+
+/// Inflates a zlib-stream gateway connection.
+/// The inflate context is shared across messages, so one of these must live as long as the connection
+#[cfg(feature = "zlib-stream")]
+pub struct ZlibStream {
+    inflater: Decompress,
+    buffer: Vec<u8>,
+}
+#[cfg(feature = "zlib-stream")]
+impl Default for ZlibStream {
+    fn default() -> Self {
+        ZlibStream {
+            inflater: Decompress::new(true),
+            buffer: Vec::new(),
+        }
+    }
+}
+#[cfg(feature = "zlib-stream")]
+impl ZlibStream {
+    /// add a binary frame. returns the inflated payload once a complete message has been received
+    pub fn push(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.buffer.extend_from_slice(data);
+        if !self.buffer.ends_with(&ZLIB_SUFFIX) {
+            return Ok(None);
+        }
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut output: Vec<u8> = Vec::with_capacity(buffer.len() * 4);
+        let mut offset = 0;
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(buffer.len().max(1024));
+            }
+            let total_in = self.inflater.total_in();
+            let output_len = output.len();
+            let status = self
+                .inflater
+                .decompress_vec(&buffer[offset..], &mut output, FlushDecompress::Sync)
+                .map_err(|err| ActorDiscordError::decode("zlib-stream", err, &buffer))?;
+            let consumed = (self.inflater.total_in() - total_in) as usize;
+            offset += consumed;
+            if offset >= buffer.len() && output.len() < output.capacity() {
+                break;
+            }
+            // a corrupt stream can end early or stop consuming input, which would loop forever
+            if status == Status::StreamEnd || (consumed == 0 && output.len() == output_len) {
+                return Err(ActorDiscordError::decode(
+                    "zlib-stream",
+                    "stream stopped inflating before the end of the message",
+                    &buffer,
+                ));
+            }
+        }
+        Ok(Some(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fragments;
+    use actix_http::ws::Item;
+
+    #[test]
+    fn fragments() {
+        let mut fragments = Fragments::default();
+        assert!(fragments.push(Item::FirstText("{\"op\":".into())).is_none());
+        assert!(fragments.push(Item::Continue("11".into())).is_none());
+        let (binary, payload) = fragments.push(Item::Last("}".into())).unwrap();
+        assert!(!binary);
+        assert_eq!(payload, b"{\"op\":11}");
+    }
+
+    #[cfg(feature = "zlib-stream")]
+    #[test]
+    fn zlib_stream() {
+        use super::ZlibStream;
+        use flate2::{Compress, Compression, FlushCompress};
+
+        let mut compress = Compress::new(Compression::default(), true);
+        let mut deflate = |input: &[u8]| {
+            let mut output = Vec::with_capacity(1024);
+            compress
+                .compress_vec(input, &mut output, FlushCompress::Sync)
+                .unwrap();
+            output
+        };
+        let first = deflate(br#"{"op":10,"d":{"heartbeat_interval":41250}}"#);
+        let second = deflate(br#"{"op":11,"d":null}"#);
+
+        let mut zlib = ZlibStream::default();
+        let (start, end) = first.split_at(first.len() / 2);
+        assert!(zlib.push(start).unwrap().is_none());
+        assert_eq!(
+            zlib.push(end).unwrap().unwrap(),
+            br#"{"op":10,"d":{"heartbeat_interval":41250}}"#
+        );
+        // the second message only inflates with the context from the first
+        assert_eq!(
+            zlib.push(&second).unwrap().unwrap(),
+            br#"{"op":11,"d":null}"#
+        );
+    }
+
+    #[cfg(feature = "zlib-stream")]
+    #[test]
+    fn zlib_stream_corrupt() {
+        use super::ZlibStream;
+
+        // a zlib header followed by garbage and the sync flush suffix
+        let mut zlib = ZlibStream::default();
+        assert!(zlib
+            .push(&[0x78, 0x9c, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff])
+            .is_err());
+
+        // a final block that ends the stream before all of the input is used
+        let mut zlib = ZlibStream::default();
+        assert!(zlib
+            .push(&[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0xff, 0xff])
+            .is_err());
+    }
+}
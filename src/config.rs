@@ -0,0 +1,203 @@
+use crate::errors::{ActorDiscordError, Result};
+use crate::types::events::SnowflakeID;
+use crate::types::gateway::Shard;
+use crate::GatewayIntents;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Everything needed to build a [crate::DiscordAPI] and [crate::DiscordBot].
+/// read with [DiscordConfig::from_env] (the variables in `.env.default`), or from a toml/json file.
+/// only `token` is required
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscordConfig {
+    pub token: String,
+    /// the REST API is served from `{url}/api/v{version}/`
+    pub url: String,
+    /// REST API and gateway version
+    pub version: u8,
+    pub retries: usize,
+    /// seconds to wait for a REST response, or the gateway's websocket handshake
+    pub timeout: Option<u64>,
+    /// seconds to wait for a REST or gateway connection to open
+    pub connect_timeout: Option<u64>,
+    pub intents: GatewayIntents,
+    /// number of shards to run. discord's recommendation when not set
+    pub shard_count: Option<u64>,
+    /// run a single shard of `shard_count`, when the shards are spread across processes
+    pub shard_id: Option<u64>,
+    pub application_id: Option<SnowflakeID>,
+    /// used to verify interaction requests
+    pub public_key: Option<String>,
+    /// permissions to ask for in the invite URL
+    pub permissions: Option<u64>,
+    pub guild_id: Option<SnowflakeID>,
+}
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        DiscordConfig {
+            token: String::new(),
+            url: "https://discord.com/".into(),
+            version: 9,
+            retries: 4,
+            timeout: None,
+            connect_timeout: None,
+            intents: GatewayIntents::non_privileged(),
+            shard_count: None,
+            shard_id: None,
+            application_id: None,
+            public_key: None,
+            permissions: None,
+            guild_id: None,
+        }
+    }
+}
+impl DiscordConfig {
+    /// read `DISCORD_*` environment variables. load `.env` first with `dotenv` to use it
+    pub fn from_env() -> Result<DiscordConfig> {
+        let defaults = DiscordConfig::default();
+        let config = DiscordConfig {
+            token: env_var("DISCORD_TOKEN")?.unwrap_or_default(),
+            url: env_var("DISCORD_URL")?.unwrap_or(defaults.url),
+            version: env_var("DISCORD_API_VERSION")?.unwrap_or(defaults.version),
+            retries: env_var("DISCORD_RETRIES")?.unwrap_or(defaults.retries),
+            timeout: env_var("DISCORD_TIMEOUT")?,
+            connect_timeout: env_var("DISCORD_CONNECT_TIMEOUT")?,
+            intents: env_var("DISCORD_INTENTS")?.unwrap_or(defaults.intents),
+            shard_count: env_var("DISCORD_SHARD_COUNT")?,
+            shard_id: env_var("DISCORD_SHARD_ID")?,
+            application_id: env_var::<u64>("DISCORD_APPLICATION_ID")?.map(SnowflakeID::from),
+            public_key: env_var("DISCORD_PUBLIC_KEY")?,
+            permissions: env_var("DISCORD_PERMISSION")?,
+            guild_id: env_var::<u64>("DISCORD_GUILD_ID")?.map(SnowflakeID::from),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(toml: &str) -> Result<DiscordConfig> {
+        let config: DiscordConfig = toml::from_str(toml).map_err(|e| config_error("toml", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_json(json: &str) -> Result<DiscordConfig> {
+        let config: DiscordConfig =
+            serde_json::from_str(json).map_err(|e| config_error("json", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// read a `.toml` or `.json` file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<DiscordConfig> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => DiscordConfig::from_toml(&contents),
+            Some("json") => DiscordConfig::from_json(&contents),
+            _ => Err(config_error(
+                "file",
+                format!("{} is not a .toml or .json file", path.display()),
+            )),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.token.trim().is_empty() {
+            return Err(config_error("token", "missing, set DISCORD_TOKEN"));
+        }
+        if let (Some(id), Some(count)) = (self.shard_id, self.shard_count) {
+            if id >= count {
+                return Err(config_error(
+                    "shard_id",
+                    format!("{} is not below shard_count {}", id, count),
+                ));
+            }
+        }
+        if self.shard_id.is_some() && self.shard_count.is_none() {
+            return Err(config_error("shard_id", "needs shard_count"));
+        }
+        Ok(())
+    }
+
+    /// the shard to run when a single one is configured
+    pub fn shard(&self) -> Option<Shard> {
+        Some(Shard {
+            id: self.shard_id?,
+            count: self.shard_count?,
+        })
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout.map(Duration::from_secs)
+    }
+}
+
+fn config_error<E: Display>(name: &str, reason: E) -> ActorDiscordError {
+    ActorDiscordError::ConfigError {
+        name: name.into(),
+        reason: reason.to_string(),
+    }
+}
+
+/// a variable that is set and not blank, parsed
+fn env_var<T: FromStr>(name: &str) -> Result<Option<T>>
+where
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) if !value.trim().is_empty() => Ok(Some(
+            value.trim().parse().map_err(|e| config_error(name, e))?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiscordConfig;
+    use crate::GatewayIntents;
+
+    #[test]
+    fn from_files() {
+        let config = DiscordConfig::from_toml(
+            r#"
+            token = "abc"
+            retries = 2
+            timeout = 10
+            intents = "GUILDS|GUILD_MESSAGES|MESSAGE_CONTENT"
+            shard_count = 4
+            shard_id = 1
+            application_id = "80351110224678912"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.url, "https://discord.com/");
+        assert_eq!(config.version, 9);
+        assert_eq!(config.retries, 2);
+        assert_eq!(config.timeout().unwrap().as_secs(), 10);
+        assert!(config.intents.message_content());
+        assert_eq!(config.shard().unwrap().id, 1);
+        assert_eq!(config.application_id.unwrap().id, 80351110224678912);
+
+        let config =
+            DiscordConfig::from_json(r#"{"token":"abc","intents":513,"permissions":8}"#).unwrap();
+        assert_eq!(
+            config.intents,
+            GatewayIntents::GUILDS | GatewayIntents::GUILD_MESSAGES
+        );
+        assert_eq!(config.permissions, Some(8));
+        assert!(config.shard().is_none());
+
+        assert!(DiscordConfig::from_json(r#"{"retries":2}"#).is_err());
+        assert!(DiscordConfig::from_json(r#"{"token":"abc","shard_id":1}"#).is_err());
+    }
+}
@@ -1,236 +1,1766 @@
+use crate::backoff::Backoff;
+use crate::bot::subscribed_events;
+use crate::compression::Fragments;
+#[cfg(feature = "zlib-stream")]
+use crate::compression::ZlibStream;
+use crate::errors::ActorDiscordError;
+use crate::errors::Result;
+#[cfg(feature = "etf")]
+use crate::etf;
+use crate::members::MemberRequests;
+use crate::ratelimit::SendLimiter;
+use crate::shard::IdentifyLimiter;
 use crate::types::events::{
-    ChannelEvent, Event, GuildChannel, GuildCreate, MessageEvent, MessageObject,
+    ChannelEvent, ConnectionState, ConnectionStatus, Event, GatewayLatency, GuildChannel,
+    GuildCreate, GuildEvent, GuildMembers, GuildMembersChunk, InviteEvent, MemberEvent,
+    MessageEvent, MessageObject, PresenceEvent, RawEvent, RawEvents, ReactionEvent, Ready,
+    ThreadEvent,
 };
-use crate::types::gateway::{GatewayHello, GatewayIdentify, GatewayMessage, GatewayReply};
-use crate::{types::gateway, DiscordAPI, GatewayIntents};
+use crate::types::gateway::{
+    CloseAction, GatewayCloseCode, GatewayCommand, GatewayEvent, GatewayIdentify, GatewayReply,
+    GatewayResume, GatewaySession, IdentifyProperties, RequestGuildMembers, Shard, UpdatePresence,
+    UpdateVoiceState,
+};
+use crate::{DiscordAPI, GatewayIntents};
 use actix_broker::{Broker, SystemBroker};
-use actix_http::ws::Frame;
-use anyhow::Result;
-use awc::ws::Message;
+use actix_http::ws::{Frame, ProtocolError};
+use awc::ws::{CloseCode, CloseReason, Message};
 use awc::Client;
-use futures::StreamExt;
+use futures::{Sink, Stream, StreamExt};
 use futures_util::sink::SinkExt as _;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::future::Future;
 use std::str::FromStr;
 #[allow(unused_imports)]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::Interval;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, Interval};
 use url::Url;
 const GATEWAY: &str = "gateway";
-pub struct DiscordBot<'a> {
-    pub api: &'a DiscordAPI,
-    pub client: Client,
+#[cfg(not(feature = "etf"))]
+const GATEWAY_ENCODING: &str = "json";
+#[cfg(feature = "etf")]
+const GATEWAY_ENCODING: &str = "etf";
+#[cfg(not(feature = "zlib-stream"))]
+const GATEWAY_COMPRESS: &str = "false";
+#[cfg(feature = "zlib-stream")]
+const GATEWAY_COMPRESS: &str = "zlib-stream";
+/// number of heartbeats the average latency is calculated over
+const LATENCY_WINDOW: usize = 10;
+/// how long a shutdown waits for queued commands to be sent before closing anyway
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+/// how long [GatewayHandle::request_guild_members] waits for every chunk, including time spent
+/// waiting for the connection to be ready
+const MEMBER_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// how [GatewayHandle::shutdown] closes the connection
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// close with 1000, discord ends the session and the bot goes offline
+    EndSession,
+    /// close with 4000, so the session stays alive for a restarted process to RESUME.
+    /// the session is published with the final [ConnectionStatus]
+    KeepSession,
+}
+impl ShutdownMode {
+    fn close_reason(self) -> CloseReason {
+        match self {
+            ShutdownMode::EndSession => CloseReason {
+                code: CloseCode::Normal,
+                description: Some("shutting down".into()),
+            },
+            ShutdownMode::KeepSession => CloseReason {
+                code: CloseCode::Other(4000),
+                description: Some("restarting".into()),
+            },
+        }
+    }
+}
+
+/// decode a dispatch's data, keeping the payload for the error
+fn decode_dispatch<T: DeserializeOwned>(event_name: &str, data: serde_json::Value) -> Result<T> {
+    T::deserialize(&data)
+        .map_err(|err| ActorDiscordError::decode(event_name, err, data.to_string().as_bytes()))
+}
+
+/// what to do once the current gateway connection ends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reconnect {
+    /// reconnect and RESUME the session (falls back to IDENTIFY if we never got a session)
+    Resume,
+    /// reconnect and IDENTIFY with a new session
+    Identify,
+    /// don't reconnect. The gateway closed with something retrying won't fix
+    Fatal {
+        code: GatewayCloseCode,
+        reason: String,
+    },
+    /// shutdown was requested and the connection was closed
+    Stop(ShutdownMode),
+}
+impl Reconnect {
+    /// decide what to do based on the close code discord sent
+    pub fn from_close_code(code: GatewayCloseCode, reason: String) -> Reconnect {
+        match code.action() {
+            CloseAction::Resume => Reconnect::Resume,
+            CloseAction::Identify => Reconnect::Identify,
+            CloseAction::Fatal => Reconnect::Fatal { code, reason },
+        }
+    }
+}
+/// sends commands to a running [GatewayConnection]. cheap to clone, so any actor can hold one.
+/// commands are queued until the connection is ready, and then sent within the gateway's
+/// limit of 120 payloads a minute
+#[derive(Debug, Clone)]
+pub struct GatewayHandle {
+    pub shard: Shard,
+    sender: mpsc::UnboundedSender<GatewayCommand>,
+    member_requests: Arc<MemberRequests>,
+    shutdown: mpsc::UnboundedSender<ShutdownMode>,
+    running: Arc<AtomicBool>,
+}
+impl GatewayHandle {
+    pub fn send(&self, command: GatewayCommand) -> Result<()> {
+        self.sender
+            .send(command)
+            .map_err(|_| ActorDiscordError::BotStopped)?;
+        Ok(())
+    }
+
+    /// send the queued commands and close the connection. `start_websocket` returns once it is
+    /// closed, after publishing a final `Closed` [ConnectionStatus].
+    /// fails with [ActorDiscordError::BotStopped] if `start_websocket` isn't running, rather than
+    /// ending the next start straight away
+    pub fn shutdown(&self, mode: ShutdownMode) -> Result<()> {
+        if !self.running.load(Ordering::SeqCst) {
+            return Err(ActorDiscordError::BotStopped);
+        }
+        self.shutdown
+            .send(mode)
+            .map_err(|_| ActorDiscordError::BotStopped)?;
+        Ok(())
+    }
+
+    /// set the bot's status and activities (op 3)
+    pub fn update_presence(&self, presence: UpdatePresence) -> Result<()> {
+        self.send(GatewayCommand::PresenceUpdate(presence))
+    }
+
+    /// join, move between or leave (with `channel_id: None`) voice channels (op 4)
+    pub fn update_voice_state(&self, voice_state: UpdateVoiceState) -> Result<()> {
+        self.send(GatewayCommand::VoiceStateUpdate(voice_state))
+    }
+
+    /// send a payload this crate has no type for
+    pub fn send_raw(&self, op: u8, d: serde_json::Value) -> Result<()> {
+        self.send(GatewayCommand::Raw { op, d })
+    }
+
+    /// request members (op 8), and wait for every GUILD_MEMBERS_CHUNK of the reply.
+    /// the nonce is set here. listing every member needs the GUILD_MEMBERS intent.
+    /// fails with [ActorDiscordError::ReplyTimeout] if the reply hasn't arrived within a minute
+    pub async fn request_guild_members(
+        &self,
+        mut request: RequestGuildMembers,
+    ) -> Result<GuildMembers> {
+        let (nonce, reply) = self.member_requests.register(request.guild_id);
+        request.nonce = Some(nonce.clone());
+        if let Err(e) = self.send(GatewayCommand::RequestGuildMembers(request)) {
+            self.member_requests.cancel(&nonce);
+            return Err(e);
+        }
+        match tokio::time::timeout(MEMBER_REQUEST_TIMEOUT, reply).await {
+            Ok(reply) => reply.map_err(|_| ActorDiscordError::ConnectionLost),
+            Err(_) => {
+                self.member_requests.cancel(&nonce);
+                Err(ActorDiscordError::ReplyTimeout(MEMBER_REQUEST_TIMEOUT))
+            }
+        }
+    }
+}
+
+pub struct GatewayConnection {
+    pub api: Arc<DiscordAPI>,
     pub web_socket: Url,
     pub intents: u64,
     pub duration: Duration,
     pub sequence_number: Option<usize>,
     pub interval: Interval,
+    /// heartbeats only start once HELLO tells us the interval
+    pub heartbeat_active: bool,
+    /// false between sending a heartbeat and receiving its ACK
+    pub heartbeat_acked: bool,
+    pub last_ack: Option<Instant>,
+    pub heartbeat_sent: Option<Instant>,
+    /// round trip times of the last few heartbeats
+    pub latencies: VecDeque<Duration>,
+    /// session id from READY, used to RESUME after a disconnect
+    pub session_id: Option<String>,
+    /// the gateway URL discord wants resumes sent to
+    pub resume_gateway_url: Option<Url>,
+    pub backoff: Backoff,
+    pub shard: Shard,
+    /// shared between shards, so IDENTIFYs respect `max_concurrency`
+    pub identify_limiter: Option<Arc<IdentifyLimiter>>,
+    /// publish dispatches as [RawEvent]s too
+    pub raw_events: RawEvents,
+    /// sent with IDENTIFY, and kept up to date by presence updates so it survives a re-identify
+    pub presence: Option<UpdatePresence>,
+    /// os, browser and device sent with IDENTIFY
+    pub properties: IdentifyProperties,
+    /// sent with IDENTIFY, 50 to 250. discord's default is 50
+    pub large_threshold: Option<u8>,
+    /// dispatches subscribers listen for, on top of the ones subscribed to with
+    /// [crate::bot::SubscribeEvents]. a warning is logged once ready for each one the intents won't deliver
+    pub expected_events: Vec<&'static str>,
+    /// the expected events were checked during this run
+    events_checked: bool,
+    pub state: ConnectionState,
+    command_sender: mpsc::UnboundedSender<GatewayCommand>,
+    commands: mpsc::UnboundedReceiver<GatewayCommand>,
+    member_requests: Arc<MemberRequests>,
+    shutdown_sender: mpsc::UnboundedSender<ShutdownMode>,
+    shutdown: mpsc::UnboundedReceiver<ShutdownMode>,
+    /// set while `start_websocket` runs, so handles know a shutdown will be seen
+    running: Arc<AtomicBool>,
+    send_limiter: SendLimiter,
+    /// code and reason of the close frame the gateway sent on this connection
+    close: Option<(GatewayCloseCode, String)>,
+    fragments: Fragments,
+    /// the inflate context is shared by every message on a connection
+    #[cfg(feature = "zlib-stream")]
+    zlib: ZlibStream,
 }
-impl<'a> DiscordBot<'a> {
-    pub fn get_client() -> Client {
-        awc::Client::builder()
-            .max_http_version(awc::http::Version::HTTP_11)
-            .finish()
+impl GatewayConnection {
+    /// the websocket client, with the API's timeouts
+    pub fn get_client(&self) -> Client {
+        let mut connector = awc::Connector::new();
+        if let Some(timeout) = self.api.connect_timeout {
+            connector = connector.timeout(timeout);
+        }
+        let mut client = awc::Client::builder()
+            .connector(connector)
+            .max_http_version(awc::http::Version::HTTP_11);
+        if let Some(timeout) = self.api.timeout {
+            client = client.timeout(timeout);
+        }
+        client.finish()
     }
-    pub async fn create(api: &'a DiscordAPI, intents: GatewayIntents) -> Result<DiscordBot<'a>> {
-        let client = Self::get_client();
-
+    pub async fn create(
+        api: Arc<DiscordAPI>,
+        intents: GatewayIntents,
+    ) -> Result<GatewayConnection> {
         //   let base_url: Url = Url::from_str(connect_addr)?.join(API_PREFIX)?;
 
         //        log::error!("URL_{}", base_url.as_str());
         let web_socket = api.get::<GatewayReply>(GATEWAY).await?;
 
         let web_socket_url = Url::from_str(&web_socket.url)?;
+        Ok(GatewayConnection::new(
+            api,
+            intents,
+            web_socket_url,
+            Shard::default(),
+            None,
+        ))
+    }
+
+    /// create a bot for a single shard of a known gateway. See [crate::ShardManager]
+    pub fn new(
+        api: Arc<DiscordAPI>,
+        intents: GatewayIntents,
+        web_socket: Url,
+        shard: Shard,
+        identify_limiter: Option<Arc<IdentifyLimiter>>,
+    ) -> GatewayConnection {
         let duration = Duration::from_secs(1);
-        Ok(DiscordBot {
-            client,
+        let (command_sender, commands) = mpsc::unbounded_channel();
+        let (shutdown_sender, shutdown) = mpsc::unbounded_channel();
+        GatewayConnection {
             api,
-            web_socket: web_socket_url,
-            //  tx,
-            //  rx,
+            web_socket,
             intents: intents.bits,
             duration,
             sequence_number: None,
             interval: tokio::time::interval(duration),
+            heartbeat_active: false,
+            heartbeat_acked: true,
+            last_ack: None,
+            heartbeat_sent: None,
+            latencies: VecDeque::with_capacity(LATENCY_WINDOW),
+            session_id: None,
+            resume_gateway_url: None,
+            backoff: Default::default(),
+            shard,
+            identify_limiter,
+            raw_events: RawEvents::Off,
+            presence: None,
+            properties: Default::default(),
+            large_threshold: None,
+            expected_events: vec![],
+            events_checked: false,
+            state: ConnectionState::Disconnected,
+            command_sender,
+            commands,
+            member_requests: Default::default(),
+            shutdown_sender,
+            running: Default::default(),
+            shutdown,
+            send_limiter: Default::default(),
+            close: None,
+            fragments: Default::default(),
+            #[cfg(feature = "zlib-stream")]
+            zlib: Default::default(),
+        }
+    }
+
+    /// a handle other actors can use to send commands through this connection
+    pub fn handle(&self) -> GatewayHandle {
+        GatewayHandle {
+            shard: self.shard,
+            sender: self.command_sender.clone(),
+            member_requests: self.member_requests.clone(),
+            shutdown: self.shutdown_sender.clone(),
+            running: self.running.clone(),
+        }
+    }
+
+    /// the subscribed and expected dispatches the intents won't deliver, with a warning logged for each
+    pub fn check_events(&self) -> Vec<&'static str> {
+        let mut events = self.expected_events.clone();
+        for event in subscribed_events() {
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        GatewayIntents::from_bits_truncate(self.intents).check_events(&events)
+    }
+
+    /// the session to RESUME, if there is one
+    pub fn session(&self) -> Option<GatewaySession> {
+        Some(GatewaySession {
+            session_id: self.session_id.clone()?,
+            sequence: self.sequence_number?,
+            resume_gateway_url: self.resume_gateway_url.as_ref().map(Url::to_string),
         })
     }
 
+    /// RESUME a session kept by a previous process instead of identifying
+    pub fn resume_session(&mut self, session: GatewaySession) -> Result<()> {
+        self.resume_gateway_url = match session.resume_gateway_url {
+            Some(url) => Some(Url::from_str(&url)?),
+            None => None,
+        };
+        self.session_id = Some(session.session_id);
+        self.sequence_number = Some(session.sequence);
+        Ok(())
+    }
+
+    /// a session can be resumed if we have been READY and have seen a sequence number
+    pub fn can_resume(&self) -> bool {
+        self.session_id.is_some() && self.sequence_number.is_some()
+    }
+
+    fn gateway_url(&self) -> Url {
+        let mut connect_ws = match (&self.resume_gateway_url, self.can_resume()) {
+            (Some(resume_url), true) => resume_url.clone(),
+            _ => self.web_socket.clone(),
+        };
+        connect_ws.set_query(Some(&format!(
+            "v={}&encoding={}&compress={}",
+            self.api.version, GATEWAY_ENCODING, GATEWAY_COMPRESS
+        )));
+        connect_ws
+    }
+
+    /// round trip time of the most recent heartbeat
+    pub fn latency(&self) -> Option<Duration> {
+        self.latencies.back().copied()
+    }
+
+    /// average round trip time over the last few heartbeats
+    pub fn average_latency(&self) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            None
+        } else {
+            Some(self.latencies.iter().sum::<Duration>() / self.latencies.len() as u32)
+        }
+    }
+
+    fn record_ack(&mut self) {
+        let now = Instant::now();
+        self.heartbeat_acked = true;
+        self.last_ack = Some(now);
+        if let Some(sent) = self.heartbeat_sent.take() {
+            if self.latencies.len() == LATENCY_WINDOW {
+                self.latencies.pop_front();
+            }
+            self.latencies.push_back(now - sent);
+            if let (Some(latest), Some(average)) = (self.latency(), self.average_latency()) {
+                log::debug!("Latency {:?} average {:?}", latest, average);
+                Broker::<SystemBroker>::issue_async(GatewayLatency {
+                    shard: self.shard,
+                    latest,
+                    average,
+                });
+            }
+        }
+    }
+
+    /// outgoing messages use the same encoding as the connection
+    #[cfg(not(feature = "etf"))]
+    fn encode(message: &GatewayCommand) -> Result<Message> {
+        let msg_json: String = serde_json::to_string(message).map_err(ActorDiscordError::encode)?;
+        Ok(Message::Text(msg_json.into()))
+    }
+
+    #[cfg(feature = "etf")]
+    fn encode(message: &GatewayCommand) -> Result<Message> {
+        let bin = etf::to_vec(message).map_err(ActorDiscordError::encode)?;
+        Ok(Message::Binary(bin.into()))
+    }
+
+    fn heartbeat_message(&self) -> Result<Message> {
+        log::debug!("Sending Heart-beart {:?}", self.sequence_number);
+        Self::encode(&GatewayCommand::Heartbeat(self.sequence_number))
+    }
+
+    /// called when the next heartbeat is due.
+    /// returns None if the previous heartbeat was never ACKed, which means the connection is a zombie
+    fn heartbeat_due(&mut self) -> Result<Option<Message>> {
+        if !self.heartbeat_acked {
+            return Ok(None);
+        }
+        let message = self.heartbeat_message()?;
+        self.heartbeat_acked = false;
+        self.heartbeat_sent = Some(Instant::now());
+        Ok(Some(message))
+    }
+
+    /// the message to send after HELLO. RESUME if we have a session, IDENTIFY otherwise
+    fn identify_or_resume(&self) -> GatewayCommand {
+        match (&self.session_id, self.sequence_number) {
+            (Some(session_id), Some(seq)) => {
+                log::info!("Resume session {} at {}", session_id, seq);
+                GatewayCommand::Resume(GatewayResume {
+                    token: self.api.token.clone(),
+                    session_id: session_id.clone(),
+                    seq,
+                })
+            }
+            _ => {
+                log::info!("Identify");
+                let mut identify = GatewayIdentify::builder(&self.api.token, self.intents)
+                    .properties(self.properties.clone())
+                    .shard(self.shard)
+                    .presence(self.presence.clone());
+                if let Some(large_threshold) = self.large_threshold {
+                    identify = identify.large_threshold(large_threshold);
+                }
+                GatewayCommand::Identify(identify.build())
+            }
+        }
+    }
+
+    /// move to a new state, publishing a [ConnectionStatus] if it changed
+    fn set_state(&mut self, state: ConnectionState) {
+        if state == self.state {
+            return;
+        }
+        let (code, reason) = match (state, &self.close) {
+            (ConnectionState::Disconnected | ConnectionState::Closed, Some((code, reason))) => {
+                (Some(*code), Some(reason.clone()))
+            }
+            _ => (None, None),
+        };
+        log::info!("Shard {} {:?} -> {:?}", self.shard, self.state, state);
+        Broker::<SystemBroker>::issue_async(ConnectionStatus {
+            shard: self.shard,
+            previous: self.state,
+            state,
+            code,
+            reason,
+            session: match state {
+                ConnectionState::Closed => self.session(),
+                _ => None,
+            },
+        });
+        self.state = state;
+    }
+
+    fn requesting_members(&self, request: &RequestGuildMembers) {
+        let intents = GatewayIntents::from_bits_truncate(self.intents);
+        if request.user_ids.is_none() && !intents.contains(GatewayIntents::GUILD_MEMBERS) {
+            log::warn!("Requesting guild members without the GUILD_MEMBERS intent");
+        }
+        if request.presences == Some(true) && !intents.contains(GatewayIntents::GUILD_PRESENCES) {
+            log::warn!("Requesting presences without the GUILD_PRESENCES intent");
+        }
+        if let Some(nonce) = &request.nonce {
+            self.member_requests.sent(nonce);
+        }
+    }
+
+    fn issue_raw(&self, event_name: &str, payload: serde_json::Value) {
+        Broker::<SystemBroker>::issue_async(RawEvent {
+            name: event_name.into(),
+            shard: self.shard,
+            sequence: self.sequence_number,
+            payload,
+        });
+    }
+
     async fn handle_ws_gateway_event(
         &mut self,
         event_name: &str,
         gateway_message: serde_json::Value,
-    ) -> Result<(bool, Option<Message>)> {
+    ) -> Result<(Option<Reconnect>, Option<Message>)> {
         match event_name {
             "GUILD_CREATE" => {
-                let gc: GuildCreate = serde_json::from_value(gateway_message)?;
-                let event = Event::GuildCreate(gc);
+                let gc: GuildCreate = decode_dispatch(event_name, gateway_message)?;
+                let event = Event::GuildCreate(self.shard, gc);
                 log::debug!("Guild Create");
                 Broker::<SystemBroker>::issue_async(event);
             }
             "READY" => {
-                // TODO log session id for resumes
-                log::debug!("READY\n{}", gateway_message)
+                log::debug!("READY\n{}", gateway_message);
+                // the session is what matters for resuming, don't lose it to a field we can't decode
+                self.session_id = gateway_message["session_id"].as_str().map(String::from);
+                self.resume_gateway_url = gateway_message["resume_gateway_url"]
+                    .as_str()
+                    .and_then(|url| Url::from_str(url).ok());
+                self.backoff.reset();
+                self.set_state(ConnectionState::Ready);
+                // subscribers have started by now
+                if !self.events_checked {
+                    self.events_checked = true;
+                    self.check_events();
+                }
+                match decode_dispatch::<Ready>(event_name, gateway_message) {
+                    Ok(ready) => {
+                        log::info!(
+                            "Session {} as {} with {} guilds",
+                            ready.session_id,
+                            ready.user.username,
+                            ready.guilds.len()
+                        );
+                        Broker::<SystemBroker>::issue_async(Event::Ready(self.shard, ready));
+                    }
+                    Err(e) => log::warn!("Session {:?} ready, {}", self.session_id, e),
+                }
+            }
+            "RESUMED" => {
+                log::info!("Resumed session");
+                self.backoff.reset();
+                self.set_state(ConnectionState::Ready);
             }
-            "MESSAGE_CREATE" | "MESSAGE_UPDATE" | "MESSAGE_DELETE" => {
+            "MESSAGE_CREATE" | "MESSAGE_UPDATE" => {
                 // log::info!("{}\n{}", event_name, gateway_message);
-                let gc: MessageObject = serde_json::from_value(gateway_message)?;
+                let gc: MessageObject = decode_dispatch(event_name, gateway_message)?;
                 let event = if event_name == "MESSAGE_CREATE" {
-                    MessageEvent::MessageCreate(gc)
-                } else if event_name == "MESSAGE_DELETE" {
-                    MessageEvent::MessageDelete(gc)
+                    MessageEvent::MessageCreate(self.shard, gc)
                 } else {
-                    MessageEvent::MessageUpdate(gc)
+                    MessageEvent::MessageUpdate(self.shard, gc)
                 };
 
                 log::debug!("Message Create/update");
                 Broker::<SystemBroker>::issue_async(event);
             }
+            "MESSAGE_DELETE" => Broker::<SystemBroker>::issue_async(MessageEvent::MessageDelete(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "MESSAGE_DELETE_BULK" => {
+                Broker::<SystemBroker>::issue_async(MessageEvent::MessageDeleteBulk(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
             "CHANNEL_UPDATE" | "CHANNEL_CREATE" | "CHANNEL_DELETE" => {
                 //  log::info!("{}\n{}", event_name, gateway_message);
-                let gc: GuildChannel = serde_json::from_value(gateway_message)?;
+                let gc: GuildChannel = decode_dispatch(event_name, gateway_message)?;
                 let event = if event_name == "CHANNEL_CREATE" {
-                    ChannelEvent::ChannelCreate(gc)
+                    ChannelEvent::ChannelCreate(self.shard, gc)
                 } else if event_name == "CHANNEL_DELETE" {
-                    ChannelEvent::ChannelDelete(gc)
+                    ChannelEvent::ChannelDelete(self.shard, gc)
                 } else {
-                    ChannelEvent::ChannelUpdate(gc)
+                    ChannelEvent::ChannelUpdate(self.shard, gc)
                 };
                 Broker::<SystemBroker>::issue_async(event);
             }
-
-            &_ => {
-                log::warn!("Unknown event {}\n{}", event_name, gateway_message)
+            "CHANNEL_PINS_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(ChannelEvent::ChannelPinsUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "WEBHOOKS_UPDATE" => Broker::<SystemBroker>::issue_async(ChannelEvent::WebhooksUpdate(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "GUILD_UPDATE" => Broker::<SystemBroker>::issue_async(GuildEvent::GuildUpdate(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "GUILD_DELETE" => Broker::<SystemBroker>::issue_async(GuildEvent::GuildDelete(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "GUILD_ROLE_CREATE" => {
+                Broker::<SystemBroker>::issue_async(GuildEvent::GuildRoleCreate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "GUILD_ROLE_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(GuildEvent::GuildRoleUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "GUILD_ROLE_DELETE" => {
+                Broker::<SystemBroker>::issue_async(GuildEvent::GuildRoleDelete(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "GUILD_BAN_ADD" => Broker::<SystemBroker>::issue_async(GuildEvent::GuildBanAdd(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "GUILD_BAN_REMOVE" => Broker::<SystemBroker>::issue_async(GuildEvent::GuildBanRemove(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "GUILD_EMOJIS_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(GuildEvent::GuildEmojisUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
             }
+            "GUILD_INTEGRATIONS_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(GuildEvent::GuildIntegrationsUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "GUILD_MEMBER_ADD" => Broker::<SystemBroker>::issue_async(MemberEvent::GuildMemberAdd(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "GUILD_MEMBER_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(MemberEvent::GuildMemberUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "GUILD_MEMBER_REMOVE" => {
+                Broker::<SystemBroker>::issue_async(MemberEvent::GuildMemberRemove(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "GUILD_MEMBERS_CHUNK" => {
+                let chunk: GuildMembersChunk = decode_dispatch(event_name, gateway_message)?;
+                log::debug!(
+                    "Members chunk {}/{} {:?}",
+                    chunk.chunk_index + 1,
+                    chunk.chunk_count,
+                    chunk.nonce
+                );
+                if let Some(chunk) = self.member_requests.chunk(chunk) {
+                    Broker::<SystemBroker>::issue_async(MemberEvent::GuildMembersChunk(
+                        self.shard, chunk,
+                    ));
+                }
+            }
+            "MESSAGE_REACTION_ADD" => {
+                Broker::<SystemBroker>::issue_async(ReactionEvent::MessageReactionAdd(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "MESSAGE_REACTION_REMOVE" => {
+                Broker::<SystemBroker>::issue_async(ReactionEvent::MessageReactionRemove(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "MESSAGE_REACTION_REMOVE_ALL" => {
+                Broker::<SystemBroker>::issue_async(ReactionEvent::MessageReactionRemoveAll(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "MESSAGE_REACTION_REMOVE_EMOJI" => {
+                Broker::<SystemBroker>::issue_async(ReactionEvent::MessageReactionRemoveEmoji(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "PRESENCE_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(PresenceEvent::PresenceUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "TYPING_START" => Broker::<SystemBroker>::issue_async(PresenceEvent::TypingStart(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "THREAD_CREATE" => Broker::<SystemBroker>::issue_async(ThreadEvent::ThreadCreate(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "THREAD_UPDATE" => Broker::<SystemBroker>::issue_async(ThreadEvent::ThreadUpdate(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "THREAD_DELETE" => Broker::<SystemBroker>::issue_async(ThreadEvent::ThreadDelete(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "THREAD_LIST_SYNC" => Broker::<SystemBroker>::issue_async(ThreadEvent::ThreadListSync(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "THREAD_MEMBER_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(ThreadEvent::ThreadMemberUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "THREAD_MEMBERS_UPDATE" => {
+                Broker::<SystemBroker>::issue_async(ThreadEvent::ThreadMembersUpdate(
+                    self.shard,
+                    decode_dispatch(event_name, gateway_message)?,
+                ))
+            }
+            "INVITE_CREATE" => Broker::<SystemBroker>::issue_async(InviteEvent::InviteCreate(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+            "INVITE_DELETE" => Broker::<SystemBroker>::issue_async(InviteEvent::InviteDelete(
+                self.shard,
+                decode_dispatch(event_name, gateway_message)?,
+            )),
+
+            &_ => match self.raw_events {
+                RawEvents::Unknown => {
+                    log::debug!("Unknown event {}", event_name);
+                    self.issue_raw(event_name, gateway_message);
+                }
+                // already published as a RawEvent before it got here
+                RawEvents::All => log::debug!("Unknown event {}", event_name),
+                RawEvents::Off => {
+                    log::warn!("Unknown event {}\n{}", event_name, gateway_message)
+                }
+            },
         }
-        Ok((true, None))
+        Ok((None, None))
     }
-    async fn handle_ws(
+    async fn handle_text(&mut self, txt: &[u8]) -> Result<(Option<Reconnect>, Option<Message>)> {
+        let event: GatewayEvent = serde_json::from_slice(txt)
+            .map_err(|err| ActorDiscordError::decode("gateway", err, txt))?;
+        self.handle_gateway_message(event).await
+    }
+
+    /// binary frames are zlib-stream compressed and/or ETF encoded, depending on the features enabled
+    async fn handle_binary(&mut self, bin: &[u8]) -> Result<(Option<Reconnect>, Option<Message>)> {
+        #[cfg(feature = "zlib-stream")]
+        let inflated = match self.zlib.push(bin) {
+            Ok(Some(inflated)) => inflated,
+            Ok(None) => return Ok((None, None)),
+            // the inflate context is broken, every later message would fail too
+            Err(e) => {
+                log::error!("zlib-stream can't be inflated, reconnecting {}", e);
+                return Ok((Some(Reconnect::Resume), Some(Self::close_resumable())));
+            }
+        };
+        #[cfg(feature = "zlib-stream")]
+        let bin: &[u8] = &inflated;
+        let event = Self::decode_binary(bin)?;
+        self.handle_gateway_message(event).await
+    }
+
+    #[cfg(not(feature = "etf"))]
+    fn decode_binary(bin: &[u8]) -> Result<GatewayEvent> {
+        serde_json::from_slice(bin).map_err(|err| ActorDiscordError::decode("gateway", err, bin))
+    }
+
+    #[cfg(feature = "etf")]
+    fn decode_binary(bin: &[u8]) -> Result<GatewayEvent> {
+        etf::from_slice(bin).map_err(|err| ActorDiscordError::decode("gateway", err, bin))
+    }
+
+    /// a complete (decoded) gateway payload
+    async fn handle_gateway_message(
         &mut self,
-        //  connection: &mut actix_codec::Framed<BoxedSocket, Codec>,
-        response: Frame,
-    ) -> Result<(bool, Option<Message>)> {
-        match response {
-            Frame::Text(txt) => {
-                let b: GatewayMessage = serde_json::from_str(&String::from_utf8_lossy(&txt))?;
-                if let Some(new_sequence) = b.s {
+        event: GatewayEvent,
+    ) -> Result<(Option<Reconnect>, Option<Message>)> {
+        match event {
+            GatewayEvent::Dispatch {
+                name,
+                sequence,
+                data,
+            } => {
+                if let Some(new_sequence) = sequence {
                     self.sequence_number = Some(new_sequence);
                 }
-                match b.op {
-                    gateway::GATEWAY => {
-                        if let Some(gateway_event_name) = b.t {
-                            return self.handle_ws_gateway_event(&gateway_event_name, b.d).await;
-                        } else {
-                            log::warn!("Gateway No Event ?? {}", &String::from_utf8_lossy(&txt));
-                        }
-                    }
-                    gateway::HELLO => {
-                        let hello: GatewayHello = serde_json::from_value(b.d)?;
-                        log::info!("Heartbeat:{}ms", hello.heartbeat_interval);
-                        self.duration = Duration::from_millis(hello.heartbeat_interval);
-                        self.interval = tokio::time::interval(self.duration);
-                        let identify = serde_json::to_value(GatewayIdentify::create(
-                            &self.api.token,
-                            self.intents,
-                        ))?;
-                        let msg_json: String = serde_json::to_string(&GatewayMessage {
-                            op: gateway::IDENTIFY,
-                            d: identify,
-                            s: None,
-                            t: None,
-                        })?;
-                        log::info!("Identify");
-                        let message = Message::Text(msg_json.into());
-                        return Ok((true, Some(message)));
-                    }
-                    gateway::ACK => {
-                        log::debug!("ACKED {}", String::from_utf8_lossy(&txt));
-                    }
-                    gateway::INVALID_SESSION => {
-                        log::warn!("INVALID session {}", b.d.as_bool().unwrap_or(false));
-                    }
-                    gateway::RECONNECT => {
-                        log::warn!("Reconnect requested {}", String::from_utf8_lossy(&txt));
-                    }
-                    _ => {
-                        log::error!("Unknown Op Code: {}", b.op)
-                    }
+                if self.raw_events == RawEvents::All {
+                    self.issue_raw(&name, data.clone());
+                }
+                return self.handle_ws_gateway_event(&name, data).await;
+            }
+            GatewayEvent::Hello(hello) => {
+                log::info!("Heartbeat:{}ms", hello.heartbeat_interval);
+                self.duration = Duration::from_millis(hello.heartbeat_interval);
+                // the first heartbeat is sent after interval * jitter, as the protocol requires
+                let jitter = self.duration.mul_f64(rand::thread_rng().gen::<f64>());
+                self.interval = tokio::time::interval_at(Instant::now() + jitter, self.duration);
+                self.heartbeat_active = true;
+                self.heartbeat_acked = true;
+                self.set_state(if self.can_resume() {
+                    ConnectionState::Resuming
+                } else {
+                    ConnectionState::Identifying
+                });
+                let message = Self::encode(&self.identify_or_resume())?;
+                return Ok((None, Some(message)));
+            }
+            GatewayEvent::HeartbeatAck => {
+                log::debug!("ACKED");
+                self.record_ack();
+            }
+            GatewayEvent::Heartbeat => {
+                log::debug!("Heartbeat requested");
+                return Ok((None, Some(self.heartbeat_message()?)));
+            }
+            GatewayEvent::InvalidSession { resumable } => {
+                log::warn!("INVALID session {}", resumable);
+                return if resumable {
+                    Ok((Some(Reconnect::Resume), Some(Self::close_resumable())))
+                } else {
+                    let close = Message::Close(Some(CloseCode::Normal.into()));
+                    Ok((Some(Reconnect::Identify), Some(close)))
+                };
+            }
+            GatewayEvent::Reconnect => {
+                log::warn!("Reconnect requested");
+                return Ok((Some(Reconnect::Resume), Some(Self::close_resumable())));
+            }
+        }
+        Ok((None, None))
+    }
+
+    async fn handle_ws(&mut self, response: Frame) -> Result<(Option<Reconnect>, Option<Message>)> {
+        match response {
+            Frame::Text(txt) => {
+                return self.handle_text(&txt).await;
+            }
+            Frame::Binary(bin) => {
+                return self.handle_binary(&bin).await;
+            }
+            Frame::Continuation(item) => {
+                if let Some((binary, payload)) = self.fragments.push(item) {
+                    return if binary {
+                        self.handle_binary(&payload).await
+                    } else {
+                        self.handle_text(&payload).await
+                    };
                 }
             }
-            Frame::Binary(_) => {}
-            Frame::Continuation(_) => {}
             Frame::Ping(p) => {
                 log::info!("Ping");
                 let pong = Message::Pong(p);
-                return Ok((true, Some(pong)));
-                //connection.send(pong).await?;
+                return Ok((None, Some(pong)));
             }
             Frame::Pong(_) => {}
             Frame::Close(b) => {
-                match b {
+                return match b {
                     Some(close) => {
-                        log::warn!(
-                            "Socket Closed xx/{}",
-                            // close.code.into(),
-                            close.description.unwrap_or_default()
-                        )
+                        let code = GatewayCloseCode::from(u16::from(close.code));
+                        let reason = close.description.unwrap_or_default();
+                        log::warn!("Socket Closed {} {}", code, reason);
+                        self.close = Some((code, reason.clone()));
+                        Ok((Some(Reconnect::from_close_code(code, reason)), None))
                     }
                     None => {
-                        log::warn!("Socket Closed no-reason")
+                        log::warn!("Socket Closed no-reason");
+                        Ok((Some(Reconnect::Resume), None))
                     }
                 };
-                return Ok((false, None));
             }
         }
-        Ok((true, None))
+        Ok((None, None))
     }
-    pub async fn start_websocket(&mut self) -> Result<()> {
-        let mut connect_ws = self.web_socket.clone();
-        connect_ws.set_query(Some("v=9&encoding=json&compress=false"));
-        log::info!("Starting Connect {}", connect_ws.as_str());
 
-        let (_resp, mut connection) = self.client.ws(connect_ws.as_str()).connect().await.unwrap();
-        // let mut interval = tokio::time::interval(duration);
-        Broker::<SystemBroker>::issue_async(Event::INIT);
+    /// closing with 1000/1001 invalidates the session, so use a different code when we want to resume
+    fn close_resumable() -> Message {
+        Message::Close(Some(CloseReason {
+            code: CloseCode::Other(4000),
+            description: Some("reconnecting".into()),
+        }))
+    }
+
+    /// encode a command, track what it changes and count it against the send limit.
+    /// a command that can't be encoded is logged and dropped, it shouldn't end the connection
+    fn command_message(&mut self, command: &GatewayCommand) -> Option<Message> {
+        let message = match Self::encode(command) {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Dropping op {} command {}", command.op(), e);
+                if let GatewayCommand::RequestGuildMembers(request) = command {
+                    if let Some(nonce) = &request.nonce {
+                        self.member_requests.cancel(nonce);
+                    }
+                }
+                return None;
+            }
+        };
+        match command {
+            GatewayCommand::PresenceUpdate(presence) => self.presence = Some(presence.clone()),
+            GatewayCommand::RequestGuildMembers(request) => self.requesting_members(request),
+            _ => {}
+        }
+        log::debug!("Sending op {}", command.op());
+        self.send_limiter.record();
+        Some(message)
+    }
+
+    /// send every queued command, waiting on the send limit when needed
+    async fn flush_commands<S>(&mut self, connection: &mut S) -> Result<()>
+    where
+        S: Sink<Message, Error = ProtocolError> + Unpin,
+    {
+        while let Ok(command) = self.commands.try_recv() {
+            if let Some(delay) = self.send_limiter.command_delay() {
+                tokio::time::sleep(delay).await;
+            }
+            if let Some(message) = self.command_message(&command) {
+                connection.send(message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// process a single gateway connection until it ends. returns what the caller should do next
+    async fn run_connection<S>(&mut self, mut connection: S) -> Result<Reconnect>
+    where
+        S: Stream<Item = Result<Frame, ProtocolError>>
+            + Sink<Message, Error = ProtocolError>
+            + Unpin,
+    {
+        self.heartbeat_active = false;
+        self.heartbeat_acked = true;
+        self.heartbeat_sent = None;
+        self.close = None;
+        self.send_limiter = Default::default();
+        self.fragments = Default::default();
+        #[cfg(feature = "zlib-stream")]
+        {
+            self.zlib = Default::default();
+        }
         loop {
             log::debug!("Starting Select");
+            let ready = self.state == ConnectionState::Ready;
+            let command_delay = self.send_limiter.command_delay();
             tokio::select! {
                 websocket = connection.next() => {
                     log::debug!("WS has a message");
-                    if let Some(websocket_r) = websocket {
-                        let response = websocket_r.unwrap();
-                        let (continu,message_send) = self.handle_ws(response).await?;
-                        if let Some(to_be_sent) = message_send {
-                             let _result =  connection.send(to_be_sent).await?;
+                    let response = match websocket {
+                        Some(Ok(response)) => response,
+                        Some(Err(e)) => {
+                            log::error!("WS error {}", e);
+                            return Ok(Reconnect::Resume);
                         }
-                        if !continu {
-                            break
+                        None => {
+                            log::warn!("WS has no message");
+                            return Ok(Reconnect::Resume);
+                        }
+                    };
+                    let (reconnect, message_send) = match self.handle_ws(response).await {
+                        Ok(result) => result,
+                        Err(e) => {
+                            log::error!("Unable to process gateway message {}", e);
+                            (None, None)
+                        }
+                    };
+                    if let Some(to_be_sent) = message_send {
+                        self.send_limiter.record();
+                        if let Err(e) = connection.send(to_be_sent).await {
+                            log::error!("WS send failed {}", e);
+                            return Ok(Reconnect::Resume);
                         }
-                    } else {
-                        log::warn!("WS has no message");
-                        break
+                    }
+                    if let Some(reconnect) = reconnect {
+                        return Ok(reconnect);
                     }
                 }
-                _ =  self.interval.tick() => {
-                    let heartbeat = serde_json::to_value(self.sequence_number)?;
-                    let msg_json : String = serde_json::to_string( &GatewayMessage{ op:gateway::HEARTBEAT, d:heartbeat,s:None,t:None})?;
-                    log::debug!("Sending Heart-beart {}", msg_json);
-                    let message= Message::Text(msg_json.into());
-                    let _result = connection.send(message).await?;
+                _ = self.interval.tick(), if self.heartbeat_active => {
+                    match self.heartbeat_due() {
+                        Ok(Some(message)) => {
+                            self.send_limiter.record();
+                            if let Err(e) = connection.send(message).await {
+                                log::error!("WS heartbeat failed {}", e);
+                                return Ok(Reconnect::Resume);
+                            }
+                        }
+                        Ok(None) => {
+                            log::warn!("No heartbeat ACK since {:?}, reconnecting", self.last_ack);
+                            let _ = connection.send(Self::close_resumable()).await;
+                            return Ok(Reconnect::Resume);
+                        }
+                        // the next tick tries again
+                        Err(e) => log::error!("Unable to encode heartbeat {}", e),
+                    }
+                }
+                Some(command) = self.commands.recv(), if ready && command_delay.is_none() => {
+                    if let Some(message) = self.command_message(&command) {
+                        if let Err(e) = connection.send(message).await {
+                            log::error!("WS send failed {}", e);
+                            return Ok(Reconnect::Resume);
+                        }
+                    }
+                }
+                Some(mode) = self.shutdown.recv() => {
+                    log::info!("Shutting down shard {} {:?}", self.shard, mode);
+                    if ready {
+                        match tokio::time::timeout(SHUTDOWN_FLUSH_TIMEOUT, self.flush_commands(&mut connection)).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => log::error!("Unable to send queued commands {}", e),
+                            Err(_) => log::warn!("Queued commands not sent within {:?}", SHUTDOWN_FLUSH_TIMEOUT),
+                        }
+                    }
+                    let close = mode.close_reason();
+                    self.close = Some((
+                        u16::from(close.code).into(),
+                        close.description.clone().unwrap_or_default(),
+                    ));
+                    let _ = connection.send(Message::Close(Some(close))).await;
+                    return Ok(Reconnect::Stop(mode));
+                }
+                // wakes the loop once the send limit allows the next command
+                _ = tokio::time::sleep(command_delay.unwrap_or_default()), if ready && command_delay.is_some() => {
+                    log::debug!("Gateway send limit reached, commands waited {:?}", command_delay);
                 }
             }
             log::debug!("end-of-loop");
         }
+    }
 
-        Ok(())
+    /// connect to the gateway and process events. If the connection drops it reconnects, with
+    /// exponential backoff, and RESUMEs so discord replays the events we missed.
+    /// only returns if the gateway closes the connection with a fatal close code, or
+    /// [GatewayHandle::shutdown] is called. Commands still queued when a shutdown
+    /// arrives between connections are kept for the next start
+    pub async fn start_websocket(&mut self) -> Result<()> {
+        let client = self.get_client();
+        self.supervise(move |url| {
+            let request = client.ws(url.as_str());
+            async move {
+                request
+                    .connect()
+                    .await
+                    .map(|(_resp, connection)| connection)
+            }
+        })
+        .await
+    }
+
+    /// the reconnect loop of [GatewayConnection::start_websocket], opening each connection with `connect`
+    async fn supervise<C, F, S, E>(&mut self, connect: C) -> Result<()>
+    where
+        C: FnMut(Url) -> F,
+        F: Future<Output = std::result::Result<S, E>>,
+        E: Display,
+        S: Stream<Item = Result<Frame, ProtocolError>>
+            + Sink<Message, Error = ProtocolError>
+            + Unpin,
+    {
+        // a shutdown that raced the end of the previous run
+        while self.shutdown.try_recv().is_ok() {}
+        self.running.store(true, Ordering::SeqCst);
+        self.events_checked = false;
+        let result = self.reconnect_loop(connect).await;
+        self.running.store(false, Ordering::SeqCst);
+        result
+    }
+
+    async fn reconnect_loop<C, F, S, E>(&mut self, mut connect: C) -> Result<()>
+    where
+        C: FnMut(Url) -> F,
+        F: Future<Output = std::result::Result<S, E>>,
+        E: Display,
+        S: Stream<Item = Result<Frame, ProtocolError>>
+            + Sink<Message, Error = ProtocolError>
+            + Unpin,
+    {
+        Broker::<SystemBroker>::issue_async(Event::INIT(self.shard));
+        loop {
+            if !self.can_resume() {
+                if let Some(limiter) = &self.identify_limiter {
+                    limiter.wait(self.shard.id).await;
+                }
+            }
+            let connect_ws = self.gateway_url();
+            log::info!(
+                "Starting Connect {} shard {}",
+                connect_ws.as_str(),
+                self.shard
+            );
+            self.set_state(ConnectionState::Connecting);
+            let reconnect = match connect(connect_ws.clone()).await {
+                Ok(connection) => self.run_connection(connection).await,
+                Err(e) => {
+                    log::error!("Unable to connect {} {}", connect_ws.as_str(), e);
+                    self.close = None;
+                    Ok(Reconnect::Resume)
+                }
+            };
+            self.member_requests.connection_lost();
+            let reconnect = match reconnect {
+                Ok(reconnect) => reconnect,
+                Err(e) => {
+                    self.set_state(ConnectionState::Closed);
+                    return Err(e);
+                }
+            };
+            if matches!(
+                reconnect,
+                Reconnect::Identify
+                    | Reconnect::Stop(ShutdownMode::EndSession)
+                    | Reconnect::Fatal { .. }
+            ) {
+                self.session_id = None;
+                self.sequence_number = None;
+            }
+            self.set_state(match reconnect {
+                Reconnect::Fatal { .. } | Reconnect::Stop(_) => ConnectionState::Closed,
+                _ => ConnectionState::Disconnected,
+            });
+            match reconnect {
+                Reconnect::Resume | Reconnect::Identify => {}
+                Reconnect::Stop(_) => return Ok(()),
+                Reconnect::Fatal { code, reason } => {
+                    log::error!("Gateway closed with {} {}, not reconnecting", code, reason);
+                    return Err(ActorDiscordError::GatewayClosed { code, reason });
+                }
+            }
+            let delay = self.backoff.next_delay();
+            log::info!("Reconnecting in {:?} resume={}", delay, self.can_resume());
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                Some(mode) = self.shutdown.recv() => {
+                    log::info!("Shutting down shard {} {:?} while disconnected", self.shard, mode);
+                    if mode == ShutdownMode::EndSession {
+                        self.session_id = None;
+                        self.sequence_number = None;
+                    }
+                    self.set_state(ConnectionState::Closed);
+                    return Ok(());
+                }
+            }
+        }
     }
 }
 #[cfg(test)]
 mod tests {
+    use crate::backoff::Backoff;
+    use crate::connection::GatewayConnection;
+    use crate::connection::{Reconnect, ShutdownMode};
+    use crate::errors::ActorDiscordError;
+    use crate::types::events::{
+        Activity, ActivityType, ChannelType, ConnectionState, ConnectionStatus, RawEvent,
+        RawEvents, Status,
+    };
+    use crate::types::gateway::{
+        GatewayCloseCode, GatewayCommand, GatewayEvent, GatewayIdentify, RequestGuildMembers,
+        Shard, UpdatePresence, UpdateVoiceState,
+    };
+    use crate::BrokerType;
+    use crate::{DiscordAPI, GatewayIntents};
+    use actix::{Actor, Context, Handler};
+    use actix_broker::{BrokerMsg, BrokerSubscribe};
+    use actix_http::ws::{Frame, ProtocolError};
+    use awc::ws::{CloseCode, CloseReason, Message};
+    use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+    use futures::{Sink, Stream, StreamExt};
+    use std::fmt::Debug;
+    use std::pin::Pin;
+    use std::str::FromStr;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::task::{Context as TaskContext, Poll};
+    use std::time::Duration;
+    use url::Url;
 
-    use crate::DiscordBot;
+    /// a websocket fed from, and sending to, channels
+    struct MockSocket {
+        incoming: UnboundedReceiver<Result<Frame, ProtocolError>>,
+        outgoing: UnboundedSender<Message>,
+    }
+    impl Stream for MockSocket {
+        type Item = Result<Frame, ProtocolError>;
+        fn poll_next(
+            mut self: Pin<&mut Self>,
+            cx: &mut TaskContext<'_>,
+        ) -> Poll<Option<Self::Item>> {
+            self.incoming.poll_next_unpin(cx)
+        }
+    }
+    impl Sink<Message> for MockSocket {
+        type Error = ProtocolError;
+        fn poll_ready(
+            self: Pin<&mut Self>,
+            _cx: &mut TaskContext<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+            let _ = self.outgoing.unbounded_send(item);
+            Ok(())
+        }
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut TaskContext<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(
+            self: Pin<&mut Self>,
+            _cx: &mut TaskContext<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+    fn mock_socket() -> (
+        MockSocket,
+        UnboundedSender<Result<Frame, ProtocolError>>,
+        UnboundedReceiver<Message>,
+    ) {
+        let (incoming_tx, incoming) = unbounded();
+        let (outgoing, outgoing_rx) = unbounded();
+        (MockSocket { incoming, outgoing }, incoming_tx, outgoing_rx)
+    }
+    /// an outgoing message, whichever encoding it was sent with
+    fn decode(message: Option<Message>) -> serde_json::Value {
+        match message {
+            Some(Message::Text(text)) => serde_json::from_str(&text).unwrap(),
+            #[cfg(feature = "etf")]
+            Some(Message::Binary(bin)) => crate::etf::from_slice(&bin).unwrap(),
+            message => panic!("expected text {:?}", message),
+        }
+    }
+
+    fn test_bot() -> GatewayConnection {
+        let api = DiscordAPI::create("token", "https://discord.com", 1).unwrap();
+        GatewayConnection::new(
+            Arc::new(api),
+            GatewayIntents::empty(),
+            Url::from_str("wss://gateway.discord.gg").unwrap(),
+            Default::default(),
+            None,
+        )
+    }
 
     #[tokio::test]
     async fn connect() {
-        let _client = DiscordBot::get_client();
+        let _client = test_bot().get_client();
+    }
+
+    #[tokio::test]
+    async fn resume_after_ready() {
+        let mut bot = test_bot();
+        assert!(matches!(
+            bot.identify_or_resume(),
+            GatewayCommand::Identify(_)
+        ));
+        assert_eq!(bot.gateway_url().host_str(), Some("gateway.discord.gg"));
+
+        bot.session_id = Some("abc".into());
+        bot.sequence_number = Some(42);
+        bot.resume_gateway_url = Some(Url::from_str("wss://resume.discord.gg").unwrap());
+        let resume = serde_json::to_value(bot.identify_or_resume()).unwrap();
+        assert_eq!(resume["op"], 6);
+        assert_eq!(resume["d"]["seq"], 42);
+        assert_eq!(resume["d"]["session_id"], "abc");
+        assert_eq!(bot.gateway_url().host_str(), Some("resume.discord.gg"));
+    }
+
+    #[actix_rt::test]
+    async fn ready() {
+        let mut bot = test_bot();
+        let ready = serde_json::from_str(
+            r#"{"v":9,"user":{"id":"80351110224678912","username":"bot","discriminator":"0001","avatar":null,"bot":true},"guilds":[{"id":"1","unavailable":true}],"session_id":"abc","resume_gateway_url":"wss://resume.discord.gg","shard":[0,1],"application":{"id":"80351110224678912","flags":0}}"#,
+        )
+        .unwrap();
+        bot.handle_ws_gateway_event("READY", ready).await.unwrap();
+        assert_eq!(bot.session_id.as_deref(), Some("abc"));
+        assert_eq!(
+            bot.resume_gateway_url.unwrap().host_str(),
+            Some("resume.discord.gg")
+        );
+
+        // a READY that doesn't match the typed struct still starts the session
+        let mut bot = test_bot();
+        let ready = serde_json::from_str(
+            r#"{"v":9,"user":"not a user","session_id":"def","resume_gateway_url":"wss://resume.discord.gg"}"#,
+        )
+        .unwrap();
+        bot.handle_ws_gateway_event("READY", ready).await.unwrap();
+        assert_eq!(bot.session_id.as_deref(), Some("def"));
+        assert_eq!(bot.state, ConnectionState::Ready);
+        assert!(bot.resume_gateway_url.is_some());
+    }
+
+    /// forwards a broker message to a channel
+    struct Subscriber<M>(tokio::sync::mpsc::UnboundedSender<M>);
+    impl<M: BrokerMsg + Debug> Actor for Subscriber<M> {
+        type Context = Context<Self>;
+        fn started(&mut self, ctx: &mut Self::Context) {
+            self.subscribe_sync::<BrokerType, M>(ctx);
+        }
+    }
+    impl<M: BrokerMsg + Debug> Handler<M> for Subscriber<M> {
+        type Result = ();
+        fn handle(&mut self, msg: M, _ctx: &mut Self::Context) {
+            self.0.send(msg).unwrap();
+        }
+    }
+    async fn subscribe<M: BrokerMsg + Debug>() -> tokio::sync::mpsc::UnboundedReceiver<M> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        Subscriber(tx).start();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        rx
+    }
+
+    #[actix_rt::test]
+    async fn raw_events() {
+        let mut rx = subscribe::<RawEvent>().await;
+
+        let mut bot = test_bot();
+        bot.raw_events = RawEvents::Unknown;
+        bot.handle_ws(Frame::Text(
+            r#"{"op":0,"s":5,"t":"MESSAGE_DELETE","d":{"id":"2","channel_id":"1"}}"#.into(),
+        ))
+        .await
+        .unwrap();
+        bot.handle_ws(Frame::Text(
+            r#"{"op":0,"s":6,"t":"SOMETHING_NEW","d":{"answer":42}}"#.into(),
+        ))
+        .await
+        .unwrap();
+        let raw = rx.recv().await.unwrap();
+        assert_eq!(raw.name, "SOMETHING_NEW");
+        assert_eq!(raw.sequence, Some(6));
+        assert_eq!(raw.payload["answer"], 42);
+    }
+
+    #[actix_rt::test]
+    async fn connection_status() {
+        let mut rx = subscribe::<ConnectionStatus>().await;
+        let mut bot = test_bot();
+        bot.backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(1));
+
+        // the first connection is ready then closed with a resumable code, the second with a fatal one
+        let (first, first_tx, _first_rx) = mock_socket();
+        for frame in [
+            Frame::Text(r#"{"op":10,"d":{"heartbeat_interval":41250}}"#.into()),
+            Frame::Text(r#"{"op":0,"s":1,"t":"RESUMED","d":{}}"#.into()),
+            Frame::Close(Some(CloseReason {
+                code: CloseCode::Other(4000),
+                description: Some("Unknown error".into()),
+            })),
+        ] {
+            first_tx.unbounded_send(Ok(frame)).unwrap();
+        }
+        let (second, second_tx, _second_rx) = mock_socket();
+        second_tx
+            .unbounded_send(Ok(Frame::Close(Some(CloseReason {
+                code: CloseCode::Other(4004),
+                description: Some("Authentication failed".into()),
+            }))))
+            .unwrap();
+        let mut sockets = vec![second, first];
+        let result = bot
+            .supervise(|_url| {
+                let socket = sockets.pop().ok_or("no more sockets");
+                async move { socket }
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(ActorDiscordError::GatewayClosed {
+                code: GatewayCloseCode::AuthenticationFailed,
+                ..
+            })
+        ));
+        assert_eq!(bot.state, ConnectionState::Closed);
+
+        let mut states = vec![];
+        for _ in 0..6 {
+            states.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(
+            states.iter().map(|s| s.state).collect::<Vec<_>>(),
+            vec![
+                ConnectionState::Connecting,
+                ConnectionState::Identifying,
+                ConnectionState::Ready,
+                ConnectionState::Disconnected,
+                ConnectionState::Connecting,
+                ConnectionState::Closed,
+            ]
+        );
+        assert_eq!(states[3].previous, ConnectionState::Ready);
+        assert_eq!(states[3].code, Some(GatewayCloseCode::UnknownError));
+        assert_eq!(states[3].reason.as_deref(), Some("Unknown error"));
+        assert_eq!(states[5].code, Some(GatewayCloseCode::AuthenticationFailed));
+    }
+
+    #[cfg(feature = "zlib-stream")]
+    #[actix_rt::test]
+    async fn corrupt_zlib_stream() {
+        let mut bot = test_bot();
+        let (socket, incoming, mut outgoing) = mock_socket();
+        incoming
+            .unbounded_send(Ok(Frame::Binary(
+                vec![0x78, 0x9c, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff].into(),
+            )))
+            .unwrap();
+        assert_eq!(bot.run_connection(socket).await.unwrap(), Reconnect::Resume);
+        assert!(matches!(outgoing.next().await, Some(Message::Close(_))));
+    }
+
+    #[test]
+    fn typed_payloads() {
+        let heartbeat = serde_json::to_string(&GatewayCommand::Heartbeat(Some(3))).unwrap();
+        assert_eq!(heartbeat, r#"{"op":1,"d":3}"#);
+        let voice = serde_json::to_value(GatewayCommand::VoiceStateUpdate(UpdateVoiceState {
+            guild_id: 1.into(),
+            channel_id: None,
+            self_mute: false,
+            self_deaf: true,
+        }))
+        .unwrap();
+        assert_eq!(voice["op"], 4);
+        assert!(voice["d"]["channel_id"].is_null());
+        let raw = GatewayCommand::Raw {
+            op: 31,
+            d: serde_json::json!({"new": true}),
+        };
+        assert_eq!(
+            serde_json::to_string(&raw).unwrap(),
+            r#"{"op":31,"d":{"new":true}}"#
+        );
+        let identify = GatewayIdentify::builder("token", 1)
+            .os("freebsd")
+            .compress(false)
+            .large_threshold(10)
+            .shard(Shard { id: 2, count: 4 })
+            .build();
+        let identify = serde_json::to_value(GatewayCommand::Identify(identify)).unwrap();
+        assert_eq!(identify["op"], 2);
+        assert_eq!(identify["d"]["properties"]["os"], "freebsd");
+        assert!(identify["d"]["properties"].get("$os").is_none());
+        assert_eq!(identify["d"]["compress"], false);
+        assert_eq!(identify["d"]["large_threshold"], 50);
+        assert_eq!(identify["d"]["shard"], serde_json::json!([2, 4]));
+        let hello: GatewayEvent =
+            serde_json::from_str(r#"{"t":null,"s":null,"op":10,"d":{"heartbeat_interval":41250}}"#)
+                .unwrap();
+        assert!(matches!(hello, GatewayEvent::Hello(h) if h.heartbeat_interval == 41250));
+        let dispatch: GatewayEvent =
+            serde_json::from_str(r#"{"t":"RESUMED","s":7,"op":0,"d":{}}"#).unwrap();
+        assert!(matches!(
+            dispatch,
+            GatewayEvent::Dispatch {
+                sequence: Some(7),
+                ..
+            }
+        ));
+        // clients send IDENTIFY, the gateway never does
+        assert!(serde_json::from_str::<GatewayEvent>(r#"{"op":2,"d":{}}"#).is_err());
+        assert!(serde_json::from_str::<GatewayEvent>(r#"{"op":5,"d":{}}"#).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn presence() {
+        let mut bot = test_bot();
+        bot.presence = Some(UpdatePresence::new(Status::Idle));
+        bot.large_threshold = Some(250);
+        bot.properties.device = "validator".into();
+        let identify = serde_json::to_value(bot.identify_or_resume()).unwrap();
+        assert_eq!(identify["d"]["presence"]["status"], "idle");
+        assert_eq!(identify["d"]["large_threshold"], 250);
+        assert_eq!(identify["d"]["properties"]["device"], "validator");
+        assert_eq!(identify["d"]["properties"]["os"], std::env::consts::OS);
+        assert_eq!(identify["d"]["shard"], serde_json::json!([0, 1]));
+        assert!(identify["d"].get("compress").is_none());
+
+        bot.session_id = Some("abc".into());
+        bot.sequence_number = Some(1);
+        let handle = bot.handle();
+        handle
+            .update_presence(
+                UpdatePresence::new(Status::Dnd).activity(Activity::watching("validators")),
+            )
+            .unwrap();
+        let (socket, incoming, mut outgoing) = mock_socket();
+        incoming
+            .unbounded_send(Ok(Frame::Text(
+                r#"{"op":10,"d":{"heartbeat_interval":41250}}"#.into(),
+            )))
+            .unwrap();
+        let sent = async {
+            // the presence waits for the session to be resumed
+            let resume = decode(outgoing.next().await);
+            assert!(outgoing.try_recv().is_err());
+            incoming
+                .unbounded_send(Ok(Frame::Text(
+                    r#"{"op":0,"s":2,"t":"RESUMED","d":{}}"#.into(),
+                )))
+                .unwrap();
+            let presence = decode(outgoing.next().await);
+            drop(incoming);
+            (resume, presence)
+        };
+        let (reconnect, (resume, presence)) = tokio::join!(bot.run_connection(socket), sent);
+        assert_eq!(reconnect.unwrap(), Reconnect::Resume);
+        assert_eq!(resume["op"], 6);
+        assert_eq!(presence["op"], 3);
+        assert_eq!(presence["d"]["status"], "dnd");
+        assert_eq!(presence["d"]["activities"][0]["type"], 3);
+        assert!(presence["d"]["activities"][0].get("url").is_none());
+        assert_eq!(bot.presence.unwrap().status, Status::Dnd);
+    }
+
+    #[actix_rt::test]
+    async fn request_guild_members() {
+        let mut bot = test_bot();
+        bot.state = ConnectionState::Ready;
+        let handle = bot.handle();
+        let (socket, incoming, mut outgoing) = mock_socket();
+        let client = async move {
+            let request = handle.request_guild_members(RequestGuildMembers {
+                guild_id: 1.into(),
+                query: Some("".into()),
+                limit: 0,
+                presences: None,
+                user_ids: None,
+                nonce: None,
+            });
+            let gateway = async {
+                let request = decode(outgoing.next().await);
+                assert_eq!(request["op"], 8);
+                let nonce = request["d"]["nonce"].as_str().unwrap();
+                for index in 0..2 {
+                    let chunk = format!(
+                        r#"{{"op":0,"s":{},"t":"GUILD_MEMBERS_CHUNK","d":{{"guild_id":"1","members":[{{"user":{{"id":"{}","username":"u","discriminator":"0001"}},"roles":[]}}],"chunk_index":{},"chunk_count":2,"nonce":"{}"}}}}"#,
+                        index + 1,
+                        index + 2,
+                        index,
+                        nonce
+                    );
+                    incoming
+                        .unbounded_send(Ok(Frame::Text(chunk.into())))
+                        .unwrap();
+                }
+            };
+            let (members, _) = tokio::join!(request, gateway);
+            drop(incoming);
+            members
+        };
+        let (reconnect, members) = tokio::join!(bot.run_connection(socket), client);
+        assert_eq!(reconnect.unwrap(), Reconnect::Resume);
+        let members = members.unwrap();
+        assert_eq!(members.guild_id.id, 1);
+        assert_eq!(members.members.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reconnect_requests() {
+        let mut bot = test_bot();
+        let (reconnect, close) = bot
+            .handle_ws(Frame::Text(r#"{"op":7,"d":null}"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(reconnect, Some(Reconnect::Resume));
+        assert!(close.is_some());
+        let (reconnect, _) = bot
+            .handle_ws(Frame::Text(r#"{"op":9,"d":false}"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(reconnect, Some(Reconnect::Identify));
+        assert_eq!(
+            Reconnect::from_close_code(4014.into(), "Disallowed intent(s)".into()),
+            Reconnect::Fatal {
+                code: GatewayCloseCode::DisallowedIntents,
+                reason: "Disallowed intent(s)".into()
+            }
+        );
+        assert_eq!(
+            Reconnect::from_close_code(GatewayCloseCode::UnknownError, "".into()),
+            Reconnect::Resume
+        );
+        assert_eq!(
+            Reconnect::from_close_code(GatewayCloseCode::SessionTimedOut, "".into()),
+            Reconnect::Identify
+        );
+        assert_eq!(u16::from(GatewayCloseCode::from(4999)), 4999);
+        let error = crate::errors::ActorDiscordError::GatewayClosed {
+            code: 4004.into(),
+            reason: "Authentication failed.".into(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Gateway closed with 4004 (authentication failed, check the bot token): Authentication failed."
+        );
+    }
+
+    #[actix_rt::test]
+    async fn typed_dispatches() {
+        let mut bot = test_bot();
+        let events = [
+            ("MESSAGE_DELETE", r#"{"id":"2","channel_id":"1"}"#),
+            (
+                "GUILD_MEMBER_ADD",
+                r#"{"guild_id":"1","user":{"id":"3","username":"a","discriminator":"0001","avatar":null},"roles":["4"],"joined_at":"2021-01-01T00:00:00+00:00","deaf":false,"mute":false}"#,
+            ),
+            (
+                "MESSAGE_REACTION_ADD",
+                r#"{"user_id":"3","channel_id":"1","message_id":"2","emoji":{"id":null,"name":"👍"}}"#,
+            ),
+            (
+                "PRESENCE_UPDATE",
+                r#"{"user":{"id":"3"},"guild_id":"1","status":"dnd","activities":[{"name":"chess","type":0}],"client_status":{"desktop":"dnd"}}"#,
+            ),
+            (
+                "THREAD_DELETE",
+                r#"{"id":"5","guild_id":"1","parent_id":"1","type":11}"#,
+            ),
+            ("GUILD_DELETE", r#"{"id":"1","unavailable":true}"#),
+            // types discord added after this crate was written
+            (
+                "PRESENCE_UPDATE",
+                r#"{"user":{"id":"3"},"guild_id":"1","status":"online","activities":[{"name":"new","type":42}],"client_status":{}}"#,
+            ),
+            (
+                "THREAD_DELETE",
+                r#"{"id":"5","guild_id":"1","parent_id":"1","type":99}"#,
+            ),
+        ];
+        for (name, payload) in events {
+            let message = serde_json::from_str(payload).unwrap();
+            assert!(
+                bot.handle_ws_gateway_event(name, message).await.is_ok(),
+                "{}",
+                name
+            );
+        }
+        let channel_type: ChannelType = serde_json::from_str("99").unwrap();
+        assert_eq!(channel_type, ChannelType::Unknown(99));
+        assert_eq!(serde_json::to_string(&channel_type).unwrap(), "99");
+        assert_eq!(
+            serde_json::from_str::<ActivityType>("2").unwrap(),
+            ActivityType::Listening
+        );
+        let bad = serde_json::from_str(r#"{"id":"2"}"#).unwrap();
+        assert!(bot
+            .handle_ws_gateway_event("MESSAGE_DELETE", bad)
+            .await
+            .is_err());
+    }
+
+    #[actix_rt::test]
+    async fn zombie_connection() {
+        let mut bot = test_bot();
+        bot.handle_ws(Frame::Text(
+            r#"{"op":10,"d":{"heartbeat_interval":41250}}"#.into(),
+        ))
+        .await
+        .unwrap();
+        assert!(bot.heartbeat_active);
+        assert!(bot.heartbeat_due().unwrap().is_some());
+        // no ACK for the previous heartbeat
+        assert!(bot.heartbeat_due().unwrap().is_none());
+
+        bot.handle_ws(Frame::Text(r#"{"op":11}"#.into()))
+            .await
+            .unwrap();
+        assert!(bot.last_ack.is_some());
+        assert!(bot.latency().is_some());
+        assert_eq!(bot.latency(), bot.average_latency());
+        assert!(bot.heartbeat_due().unwrap().is_some());
+
+        // discord asking for a heartbeat gets one straight away
+        let (_, heartbeat) = bot
+            .handle_ws(Frame::Text(r#"{"op":1,"d":null}"#.into()))
+            .await
+            .unwrap();
+        assert!(heartbeat.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn shutdown() {
+        for (mode, code) in [
+            (ShutdownMode::EndSession, CloseCode::Normal),
+            (ShutdownMode::KeepSession, CloseCode::Other(4000)),
+        ] {
+            let mut bot = test_bot();
+            bot.state = ConnectionState::Ready;
+            let handle = bot.handle();
+            handle
+                .update_presence(UpdatePresence::new(Status::Idle))
+                .unwrap();
+            // only accepted while start_websocket runs
+            assert!(matches!(
+                handle.shutdown(mode),
+                Err(ActorDiscordError::BotStopped)
+            ));
+            bot.running.store(true, Ordering::SeqCst);
+            handle.shutdown(mode).unwrap();
+            let (socket, _incoming, mut outgoing) = mock_socket();
+            let reconnect = bot.run_connection(socket).await.unwrap();
+            assert_eq!(reconnect, Reconnect::Stop(mode));
+            // queued commands are sent before the close frame
+            assert_eq!(decode(outgoing.next().await)["op"], 3);
+            match outgoing.next().await {
+                Some(Message::Close(Some(reason))) => assert_eq!(reason.code, code),
+                message => panic!("expected close {:?}", message),
+            }
+            assert_eq!(bot.close.unwrap().0, u16::from(code).into());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn keep_session() {
+        let mut rx = subscribe::<ConnectionStatus>().await;
+        let mut bot = test_bot();
+        assert!(bot.session().is_none());
+        bot.session_id = Some("abc".into());
+        bot.sequence_number = Some(7);
+        bot.resume_gateway_url = Some(Url::from_str("wss://resume.discord.gg").unwrap());
+        let session = bot.session().unwrap();
+        bot.set_state(ConnectionState::Closed);
+        assert_eq!(rx.recv().await.unwrap().session, Some(session.clone()));
+
+        // a restarted process resumes the session it was handed
+        let mut restarted = test_bot();
+        restarted.resume_session(session).unwrap();
+        assert!(restarted.can_resume());
+        assert_eq!(
+            restarted.gateway_url().host_str(),
+            Some("resume.discord.gg")
+        );
+        let resume = serde_json::to_value(restarted.identify_or_resume()).unwrap();
+        assert_eq!(resume["op"], 6);
+        assert_eq!(resume["d"]["seq"], 7);
     }
 }
@@ -1,14 +1,15 @@
-use crate::types::events::{ChannelEvent, Event, MessageEvent};
-use crate::BrokerType;
+use crate::bot::SubscribeEvents;
+use crate::errors::Result;
+use crate::types::events::{ChannelEvent, Event, MessageEvent, SnowflakeID};
 use crate::DiscordAPI;
 use actix::{Actor, Context, ContextFutureSpawner, Handler, WrapFuture};
-use actix_broker::BrokerSubscribe;
-use anyhow::Result;
 
 pub struct ExampleDiscordActor {
     pub token: String,
     pub connect_addr: String,
     pub max_retries: usize,
+    /// the bot's own user, from READY
+    pub user_id: Option<SnowflakeID>,
 }
 
 impl ExampleDiscordActor {
@@ -17,6 +18,7 @@ impl ExampleDiscordActor {
             token: token.into(),
             connect_addr: connect_addr.into(),
             max_retries: retries,
+            user_id: None,
         })
     }
 }
@@ -24,9 +26,9 @@ impl Actor for ExampleDiscordActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        self.subscribe_sync::<BrokerType, Event>(ctx);
-        self.subscribe_sync::<BrokerType, MessageEvent>(ctx);
-        self.subscribe_sync::<BrokerType, ChannelEvent>(ctx);
+        self.subscribe_events::<Event>(ctx);
+        self.subscribe_events::<MessageEvent>(ctx);
+        self.subscribe_events::<ChannelEvent>(ctx);
         log::info!("Discord Example Actor Started")
     }
 }
@@ -36,7 +38,7 @@ impl Handler<Event> for ExampleDiscordActor {
 
     fn handle(&mut self, msg: Event, ctx: &mut Self::Context) -> Self::Result {
         match msg {
-            Event::INIT => {
+            Event::INIT(_) => {
                 log::info!("IN INIT");
                 match DiscordAPI::create(&self.token, &self.connect_addr, self.max_retries) {
                     Ok(api) => {
@@ -54,17 +56,22 @@ impl Handler<Event> for ExampleDiscordActor {
                     Err(e) => log::error!("ERR {}", e),
                 };
             }
-            Event::GuildCreate(_) => {}
+            Event::Ready(_, ready) => self.user_id = Some(ready.user.id),
+            Event::GuildCreate(_, _) => {}
         }
     }
 }
 impl Handler<MessageEvent> for ExampleDiscordActor {
     type Result = ();
     fn handle(&mut self, msg: MessageEvent, _ctx: &mut Self::Context) {
-        match msg {
-            MessageEvent::MessageCreate(_) => {}
-            MessageEvent::MessageUpdate(_) => {}
-            MessageEvent::MessageDelete(_) => {}
+        match &msg {
+            MessageEvent::MessageCreate(_, message) if Some(message.author.id) == self.user_id => {
+                return;
+            }
+            MessageEvent::MessageCreate(_, _) => {}
+            MessageEvent::MessageUpdate(_, _) => {}
+            MessageEvent::MessageDelete(_, _) => {}
+            MessageEvent::MessageDeleteBulk(_, _) => {}
         };
         log::info!("MEvent {:?}", msg);
     }
@@ -1,11 +1,174 @@
+use crate::types::gateway::GatewayCloseCode;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use thiserror::Error;
 
+/// an error response from the REST API. `code` is discord's JSON error code, 0 if the body had none
+#[derive(Error, Debug, Clone, Serialize, Deserialize)]
+#[error("Discord API error {status} {code}: {message}")]
+pub struct DiscordApiError {
+    /// HTTP status
+    #[serde(default)]
+    pub status: u16,
+    #[serde(default)]
+    pub code: u32,
+    #[serde(default)]
+    pub message: String,
+    /// field validation errors, nested like the request body. see [DiscordApiError::field_errors]
+    #[serde(default)]
+    pub errors: Option<serde_json::Value>,
+}
+
+/// a single field validation error, `path` is e.g. `embeds.0.title`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub path: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl DiscordApiError {
+    pub const UNKNOWN_CHANNEL: u32 = 10003;
+    pub const UNKNOWN_GUILD: u32 = 10004;
+    pub const UNKNOWN_MEMBER: u32 = 10007;
+    pub const UNKNOWN_MESSAGE: u32 = 10008;
+    pub const UNKNOWN_ROLE: u32 = 10011;
+    pub const MISSING_ACCESS: u32 = 50001;
+    pub const MISSING_PERMISSIONS: u32 = 50013;
+    pub const INVALID_FORM_BODY: u32 = 50035;
+
+    /// parse an error body. bodies that aren't discord's JSON (e.g. from a proxy) end up in `message`
+    pub fn from_body(status: u16, body: &[u8]) -> DiscordApiError {
+        match serde_json::from_slice::<DiscordApiError>(body) {
+            Ok(error) => DiscordApiError { status, ..error },
+            Err(_) => DiscordApiError {
+                status,
+                code: 0,
+                message: String::from_utf8_lossy(body).into(),
+                errors: None,
+            },
+        }
+    }
+
+    pub fn is_unknown_channel(&self) -> bool {
+        self.code == Self::UNKNOWN_CHANNEL
+    }
+    pub fn is_unknown_guild(&self) -> bool {
+        self.code == Self::UNKNOWN_GUILD
+    }
+    pub fn is_unknown_message(&self) -> bool {
+        self.code == Self::UNKNOWN_MESSAGE
+    }
+    pub fn is_missing_access(&self) -> bool {
+        self.code == Self::MISSING_ACCESS
+    }
+    pub fn is_missing_permissions(&self) -> bool {
+        self.code == Self::MISSING_PERMISSIONS
+    }
+    pub fn is_not_found(&self) -> bool {
+        self.status == 404
+    }
+
+    /// every error in the `errors` tree, flattened
+    pub fn field_errors(&self) -> Vec<FieldError> {
+        let mut field_errors = vec![];
+        if let Some(errors) = &self.errors {
+            collect_field_errors(errors, "", &mut field_errors);
+        }
+        field_errors
+    }
+}
+
+fn collect_field_errors(value: &serde_json::Value, path: &str, found: &mut Vec<FieldError>) {
+    let object = match value.as_object() {
+        Some(object) => object,
+        None => return,
+    };
+    for (key, value) in object {
+        if key == "_errors" {
+            for error in value.as_array().into_iter().flatten() {
+                found.push(FieldError {
+                    path: path.into(),
+                    code: error["code"].as_str().unwrap_or_default().into(),
+                    message: error["message"].as_str().unwrap_or_default().into(),
+                });
+            }
+        } else if path.is_empty() {
+            collect_field_errors(value, key, found);
+        } else {
+            collect_field_errors(value, &format!("{}.{}", path, key), found);
+        }
+    }
+}
+
+pub type Result<T, E = ActorDiscordError> = std::result::Result<T, E>;
+
+/// every error this crate returns
 #[derive(Error, Debug)]
 pub enum ActorDiscordError {
-    #[error("ResponseError HTTP(s) Error")]
-    ResponseError(),
+    /// the request couldn't be sent, or the response couldn't be read
     #[error("HTTP(s) Error {url:?} {err:?}")]
-    ResponseErrorMsg { url: String, err: String },
-    #[error("Too many retries")]
-    RetryError,
+    Http { url: String, err: String },
+    /// a payload that didn't match the type it was decoded into
+    #[error("Unable to decode {source_name}: {err}")]
+    Decode {
+        /// the URL, or `gateway`
+        source_name: String,
+        err: String,
+        body: String,
+    },
+    #[error("Unable to encode {0}")]
+    Encode(String),
+    #[error(transparent)]
+    Api(#[from] DiscordApiError),
+    /// still rate limited after every retry
+    #[error("Rate limited on {url:?} after {retries} retries")]
+    RateLimited { url: String, retries: usize },
+    #[error("Invalid URL {0}")]
+    Url(#[from] url::ParseError),
+    #[error("Websocket error {0}")]
+    WebSocket(String),
+    #[error("Gateway closed with {code}: {reason}")]
+    GatewayClosed {
+        code: GatewayCloseCode,
+        reason: String,
+    },
+    #[error("DiscordBot is no longer running")]
+    BotStopped,
+    #[error("Gateway connection lost before the reply arrived")]
+    ConnectionLost,
+    #[error("No reply from the gateway within {0:?}")]
+    ReplyTimeout(Duration),
+    #[error("Unknown gateway intent {0:?}")]
+    UnknownIntent(String),
+    #[error("Config {name}: {reason}")]
+    ConfigError { name: String, reason: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+impl ActorDiscordError {
+    pub(crate) fn decode<E: std::fmt::Display>(source_name: &str, err: E, body: &[u8]) -> Self {
+        ActorDiscordError::Decode {
+            source_name: source_name.into(),
+            err: err.to_string(),
+            body: String::from_utf8_lossy(body).into(),
+        }
+    }
+
+    pub(crate) fn encode<E: std::fmt::Display>(err: E) -> Self {
+        ActorDiscordError::Encode(err.to_string())
+    }
+
+    /// the API error response, if that is what this is
+    pub fn api_error(&self) -> Option<&DiscordApiError> {
+        match self {
+            ActorDiscordError::Api(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl From<actix_http::ws::ProtocolError> for ActorDiscordError {
+    fn from(err: actix_http::ws::ProtocolError) -> Self {
+        ActorDiscordError::WebSocket(err.to_string())
+    }
 }
@@ -0,0 +1,309 @@
+use super::error::{Error, Result};
+use super::*;
+use serde::de::value::{SeqDeserializer, StrDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use std::convert::TryInto;
+
+/// decode a complete ETF payload (including the version byte)
+pub fn from_slice<T: DeserializeOwned>(input: &[u8]) -> Result<T> {
+    let mut deserializer = Deserializer::from_slice(input)?;
+    let value = T::deserialize(&mut deserializer)?;
+    if deserializer.input.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::TrailingBytes(deserializer.input.len()))
+    }
+}
+
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+}
+impl<'de> Deserializer<'de> {
+    pub fn from_slice(input: &'de [u8]) -> Result<Self> {
+        let mut deserializer = Deserializer { input };
+        match deserializer.read_u8()? {
+            VERSION => Ok(deserializer),
+            version => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.input.len() < len {
+            return Err(Error::Eof);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+    fn read_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+    fn read_f64(&mut self) -> Result<f64> {
+        Ok(f64::from_be_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+    fn read_str(&mut self, len: usize) -> Result<&'de str> {
+        std::str::from_utf8(self.read_bytes(len)?).map_err(|_| Error::InvalidUtf8)
+    }
+
+    /// the name of the atom, if the next term is one. the term is consumed only if it is an atom
+    fn read_atom(&mut self) -> Result<Option<&'de str>> {
+        let saved = self.input;
+        let name = match self.read_u8()? {
+            ATOM_EXT | ATOM_UTF8_EXT => {
+                let len = self.read_u16()? as usize;
+                self.read_str(len)?
+            }
+            SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => {
+                let len = self.read_u8()? as usize;
+                self.read_str(len)?
+            }
+            _ => {
+                self.input = saved;
+                return Ok(None);
+            }
+        };
+        Ok(Some(name))
+    }
+
+    /// atoms and binaries are both used as strings
+    fn read_name(&mut self) -> Result<Option<&'de str>> {
+        if let Some(atom) = self.read_atom()? {
+            return Ok(Some(atom));
+        }
+        if self.input.first() == Some(&BINARY_EXT) {
+            self.read_u8()?;
+            let len = self.read_u32()? as usize;
+            return Ok(Some(self.read_str(len)?));
+        }
+        Ok(None)
+    }
+
+    fn visit_big<V: Visitor<'de>>(&mut self, len: usize, visitor: V) -> Result<V::Value> {
+        let negative = self.read_u8()? != 0;
+        let digits = self.read_bytes(len)?;
+        let mut value: u64 = 0;
+        for (i, digit) in digits.iter().enumerate() {
+            if *digit == 0 {
+                continue;
+            }
+            if i >= 8 {
+                return Err(Error::IntegerOverflow);
+            }
+            value |= (*digit as u64) << (8 * i);
+        }
+        if !negative {
+            visitor.visit_u64(value)
+        } else if value <= i64::MAX as u64 + 1 {
+            visitor.visit_i64((value as i64).wrapping_neg())
+        } else {
+            Err(Error::IntegerOverflow)
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(atom) = self.read_atom()? {
+            return match atom {
+                "nil" => visitor.visit_unit(),
+                "true" => visitor.visit_bool(true),
+                "false" => visitor.visit_bool(false),
+                _ => visitor.visit_borrowed_str(atom),
+            };
+        }
+        match self.read_u8()? {
+            SMALL_INTEGER_EXT => visitor.visit_u8(self.read_u8()?),
+            INTEGER_EXT => visitor.visit_i32(self.read_i32()?),
+            NEW_FLOAT_EXT => visitor.visit_f64(self.read_f64()?),
+            FLOAT_EXT => {
+                let float = self.read_str(31)?.trim_end_matches('\0');
+                visitor.visit_f64(float.parse().map_err(de::Error::custom)?)
+            }
+            BINARY_EXT => {
+                let len = self.read_u32()? as usize;
+                let bytes = self.read_bytes(len)?;
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => visitor.visit_borrowed_str(s),
+                    Err(_) => visitor.visit_borrowed_bytes(bytes),
+                }
+            }
+            STRING_EXT => {
+                // erlang encodes short lists of bytes this way
+                let len = self.read_u16()? as usize;
+                let bytes = self.read_bytes(len)?;
+                visitor.visit_seq(SeqDeserializer::new(bytes.iter().copied()))
+            }
+            NIL_EXT => visitor.visit_seq(Access {
+                de: self,
+                remaining: 0,
+            }),
+            LIST_EXT => {
+                let remaining = self.read_u32()? as usize;
+                let value = visitor.visit_seq(Access {
+                    de: &mut *self,
+                    remaining,
+                })?;
+                match self.read_u8()? {
+                    NIL_EXT => Ok(value),
+                    _ => Err(Error::ImproperList),
+                }
+            }
+            SMALL_TUPLE_EXT => {
+                let remaining = self.read_u8()? as usize;
+                visitor.visit_seq(Access {
+                    de: self,
+                    remaining,
+                })
+            }
+            LARGE_TUPLE_EXT => {
+                let remaining = self.read_u32()? as usize;
+                visitor.visit_seq(Access {
+                    de: self,
+                    remaining,
+                })
+            }
+            MAP_EXT => {
+                let remaining = self.read_u32()? as usize;
+                visitor.visit_map(Access {
+                    de: self,
+                    remaining,
+                })
+            }
+            SMALL_BIG_EXT => {
+                let len = self.read_u8()? as usize;
+                self.visit_big(len, visitor)
+            }
+            LARGE_BIG_EXT => {
+                let len = self.read_u32()? as usize;
+                self.visit_big(len, visitor)
+            }
+            tag => Err(Error::UnsupportedTag(tag)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let saved = self.input;
+        if self.read_atom()? == Some("nil") {
+            visitor.visit_none()
+        } else {
+            self.input = saved;
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        // unit variants are sent as a name, the others as a single entry map {variant => value}
+        if let Some(name) = self.read_name()? {
+            let variant: StrDeserializer<Error> = name.into_deserializer();
+            return visitor.visit_enum(variant);
+        }
+        match self.read_u8()? {
+            MAP_EXT if self.read_u32()? == 1 => visitor.visit_enum(Enum { de: self }),
+            _ => Err(de::Error::custom("expected an enum")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// elements of a list/tuple, or entries of a map
+struct Access<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    remaining: usize,
+}
+impl<'de, 'a> de::SeqAccess<'de> for Access<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+impl<'de, 'a> de::MapAccess<'de> for Access<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+struct Enum<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+}
+impl<'de, 'a> de::EnumAccess<'de> for Enum<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+impl<'de, 'a> de::VariantAccess<'de> for Enum<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(self.de)
+    }
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.de)
+    }
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
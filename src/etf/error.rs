@@ -0,0 +1,34 @@
+use std::fmt::Display;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("ETF unexpected end of input")]
+    Eof,
+    #[error("ETF unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("ETF unsupported tag {0}")]
+    UnsupportedTag(u8),
+    #[error("ETF invalid UTF-8 string")]
+    InvalidUtf8,
+    #[error("ETF integer doesn't fit in 64 bits")]
+    IntegerOverflow,
+    #[error("ETF improper list")]
+    ImproperList,
+    #[error("ETF trailing bytes {0}")]
+    TrailingBytes(usize),
+    #[error("ETF {0}")]
+    Message(String),
+}
+impl serde::de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+impl serde::ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
@@ -0,0 +1,98 @@
+//! [Erlang Term Format](https://www.erlang.org/doc/apps/erts/erl_ext_dist.html) encoding for the gateway.
+//!
+//! Supports the subset of terms discord sends and accepts.
+//! Atoms `nil`, `true` and `false` map to `null` and booleans, other atoms and binaries map to strings,
+//! lists and tuples to sequences, and maps to maps (or structs).
+mod de;
+mod error;
+mod ser;
+
+pub use de::{from_slice, Deserializer};
+pub use error::{Error, Result};
+pub use ser::{to_vec, Serializer};
+
+pub(crate) const VERSION: u8 = 131;
+pub(crate) const NEW_FLOAT_EXT: u8 = 70;
+pub(crate) const SMALL_INTEGER_EXT: u8 = 97;
+pub(crate) const INTEGER_EXT: u8 = 98;
+pub(crate) const FLOAT_EXT: u8 = 99;
+pub(crate) const ATOM_EXT: u8 = 100;
+pub(crate) const SMALL_TUPLE_EXT: u8 = 104;
+pub(crate) const LARGE_TUPLE_EXT: u8 = 105;
+pub(crate) const NIL_EXT: u8 = 106;
+pub(crate) const STRING_EXT: u8 = 107;
+pub(crate) const LIST_EXT: u8 = 108;
+pub(crate) const BINARY_EXT: u8 = 109;
+pub(crate) const SMALL_BIG_EXT: u8 = 110;
+pub(crate) const LARGE_BIG_EXT: u8 = 111;
+pub(crate) const SMALL_ATOM_EXT: u8 = 115;
+pub(crate) const MAP_EXT: u8 = 116;
+pub(crate) const ATOM_UTF8_EXT: u8 = 118;
+pub(crate) const SMALL_ATOM_UTF8_EXT: u8 = 119;
+
+#[cfg(test)]
+mod tests {
+    use super::{from_slice, to_vec};
+    use crate::types::events::GuildChannel;
+    use crate::types::gateway::{GatewayCommand, GatewayEvent, GatewayIdentify};
+
+    /// HELLO, with atom keys as the gateway sends them
+    const HELLO: [u8; 120] = [
+        131, 116, 0, 0, 0, 4, 119, 1, 116, 119, 3, 110, 105, 108, 119, 1, 115, 119, 3, 110, 105,
+        108, 119, 2, 111, 112, 97, 10, 119, 1, 100, 116, 0, 0, 0, 2, 119, 18, 104, 101, 97, 114,
+        116, 98, 101, 97, 116, 95, 105, 110, 116, 101, 114, 118, 97, 108, 98, 0, 0, 161, 34, 119,
+        6, 95, 116, 114, 97, 99, 101, 108, 0, 0, 0, 1, 109, 0, 0, 0, 40, 91, 34, 103, 97, 116, 101,
+        119, 97, 121, 45, 112, 114, 100, 45, 109, 97, 105, 110, 45, 56, 53, 56, 100, 34, 44, 123,
+        34, 109, 105, 99, 114, 111, 115, 34, 58, 48, 46, 48, 125, 93, 106,
+    ];
+    /// CHANNEL_CREATE dispatch, snowflakes are sent as big integers
+    const CHANNEL_CREATE: [u8; 147] = [
+        131, 116, 0, 0, 0, 4, 119, 1, 116, 119, 14, 67, 72, 65, 78, 78, 69, 76, 95, 67, 82, 69, 65,
+        84, 69, 119, 1, 115, 97, 2, 119, 2, 111, 112, 97, 0, 119, 1, 100, 116, 0, 0, 0, 7, 119, 2,
+        105, 100, 110, 8, 0, 40, 0, 196, 31, 3, 224, 166, 11, 119, 4, 116, 121, 112, 101, 97, 0,
+        119, 4, 110, 97, 109, 101, 109, 0, 0, 0, 7, 103, 101, 110, 101, 114, 97, 108, 119, 8, 112,
+        111, 115, 105, 116, 105, 111, 110, 97, 3, 119, 9, 112, 97, 114, 101, 110, 116, 95, 105,
+        100, 119, 3, 110, 105, 108, 119, 8, 103, 117, 105, 108, 100, 95, 105, 100, 110, 8, 0, 34,
+        0, 196, 31, 3, 224, 166, 11, 119, 4, 110, 115, 102, 119, 119, 5, 102, 97, 108, 115, 101,
+    ];
+
+    #[test]
+    fn hello() {
+        let message: GatewayEvent = from_slice(&HELLO).unwrap();
+        assert!(matches!(message, GatewayEvent::Hello(hello) if hello.heartbeat_interval == 41250));
+    }
+
+    #[test]
+    fn dispatch() {
+        let (name, sequence, data) = match from_slice(&CHANNEL_CREATE).unwrap() {
+            GatewayEvent::Dispatch {
+                name,
+                sequence,
+                data,
+            } => (name, sequence, data),
+            event => panic!("not a dispatch {:?}", event),
+        };
+        assert_eq!(name, "CHANNEL_CREATE");
+        assert_eq!(sequence, Some(2));
+        let channel: GuildChannel = serde_json::from_value(data).unwrap();
+        assert_eq!(channel.id.id, 839604684573638696);
+        assert_eq!(channel.guild_id.unwrap().id, 839604684573638690);
+        assert_eq!(channel.name, "general");
+        assert!(channel.parent_id.is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let identify = GatewayIdentify::create("token", 1 << 14 | 1, Default::default());
+        let encoded = to_vec(&GatewayCommand::Identify(identify.clone())).unwrap();
+        let decoded: serde_json::Value = from_slice(&encoded).unwrap();
+        assert_eq!(decoded["op"], 2);
+        assert_eq!(decoded["d"], serde_json::to_value(&identify).unwrap());
+        let big: u64 = from_slice(&to_vec(&839604684573638696u64).unwrap()).unwrap();
+        assert_eq!(big, 839604684573638696);
+        let negative: i64 = from_slice(&to_vec(&-5_000_000_000i64).unwrap()).unwrap();
+        assert_eq!(negative, -5_000_000_000);
+        let empty: Vec<u64> = from_slice(&to_vec(&Vec::<u64>::new()).unwrap()).unwrap();
+        assert!(empty.is_empty());
+    }
+}
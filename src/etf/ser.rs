@@ -0,0 +1,324 @@
+use super::error::{Error, Result};
+use super::*;
+use serde::ser::{self, Serialize};
+
+/// encode a value as a complete ETF payload (including the version byte)
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut serializer = Serializer {
+        output: vec![VERSION],
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+pub struct Serializer {
+    output: Vec<u8>,
+}
+impl Serializer {
+    fn write_atom(&mut self, name: &str) {
+        self.output.push(SMALL_ATOM_UTF8_EXT);
+        self.output.push(name.len() as u8);
+        self.output.extend_from_slice(name.as_bytes());
+    }
+    fn write_binary(&mut self, bytes: &[u8]) {
+        self.output.push(BINARY_EXT);
+        self.output
+            .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.output.extend_from_slice(bytes);
+    }
+    fn write_big(&mut self, negative: bool, magnitude: u64) {
+        let digits = magnitude.to_le_bytes();
+        let len = 8 - (magnitude.leading_zeros() / 8) as usize;
+        self.output.push(SMALL_BIG_EXT);
+        self.output.push(len as u8);
+        self.output.push(negative as u8);
+        self.output.extend_from_slice(&digits[..len]);
+    }
+    fn write_i64(&mut self, v: i64) {
+        if (0..=255).contains(&v) {
+            self.output.push(SMALL_INTEGER_EXT);
+            self.output.push(v as u8);
+        } else if v >= i32::MIN as i64 && v <= i32::MAX as i64 {
+            self.output.push(INTEGER_EXT);
+            self.output.extend_from_slice(&(v as i32).to_be_bytes());
+        } else {
+            self.write_big(v < 0, v.unsigned_abs());
+        }
+    }
+    fn write_u64(&mut self, v: u64) {
+        if v <= i32::MAX as u64 {
+            self.write_i64(v as i64)
+        } else {
+            self.write_big(false, v)
+        }
+    }
+    /// the length of lists and maps is patched in once they are complete
+    fn start(&mut self, tag: u8) -> Compound<'_> {
+        let header = self.output.len();
+        self.output.push(tag);
+        self.output.extend_from_slice(&0u32.to_be_bytes());
+        Compound {
+            ser: self,
+            header,
+            count: 0,
+        }
+    }
+    /// `{variant => ...}`, used for non unit enum variants
+    fn start_variant(&mut self, variant: &str) {
+        self.output.push(MAP_EXT);
+        self.output.extend_from_slice(&1u32.to_be_bytes());
+        self.write_binary(variant.as_bytes());
+    }
+}
+
+pub struct Compound<'a> {
+    ser: &'a mut Serializer,
+    header: usize,
+    count: u32,
+}
+impl<'a> Compound<'a> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.count += 1;
+        value.serialize(&mut *self.ser)
+    }
+    fn end_list(self) -> Result<()> {
+        if self.count == 0 {
+            self.ser.output.truncate(self.header);
+        } else {
+            self.ser.output[self.header + 1..self.header + 5]
+                .copy_from_slice(&self.count.to_be_bytes());
+        }
+        self.ser.output.push(NIL_EXT);
+        Ok(())
+    }
+    fn end_map(self) -> Result<()> {
+        self.ser.output[self.header + 1..self.header + 5]
+            .copy_from_slice(&self.count.to_be_bytes());
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.write_atom(if v { "true" } else { "false" });
+        Ok(())
+    }
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.write_i64(v as i64);
+        Ok(())
+    }
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.write_i64(v as i64);
+        Ok(())
+    }
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.write_i64(v as i64);
+        Ok(())
+    }
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.write_i64(v);
+        Ok(())
+    }
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.write_u64(v as u64);
+        Ok(())
+    }
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.write_u64(v as u64);
+        Ok(())
+    }
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.write_u64(v as u64);
+        Ok(())
+    }
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.write_u64(v);
+        Ok(())
+    }
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.output.push(NEW_FLOAT_EXT);
+        self.output.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.write_binary(v.as_bytes());
+        Ok(())
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.write_binary(v);
+        Ok(())
+    }
+    fn serialize_none(self) -> Result<()> {
+        self.serialize_unit()
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<()> {
+        self.write_atom("nil");
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_str(variant)
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.start_variant(variant);
+        value.serialize(self)
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>> {
+        Ok(self.start(LIST_EXT))
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.start(LIST_EXT))
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.start(LIST_EXT))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>> {
+        self.start_variant(variant);
+        Ok(self.start(LIST_EXT))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>> {
+        Ok(self.start(MAP_EXT))
+    }
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.start(MAP_EXT))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>> {
+        self.start_variant(variant);
+        Ok(self.start(MAP_EXT))
+    }
+}
+
+impl<'a> ser::SerializeSeq for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+    fn end(self) -> Result<()> {
+        self.end_list()
+    }
+}
+impl<'a> ser::SerializeTuple for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+    fn end(self) -> Result<()> {
+        self.end_list()
+    }
+}
+impl<'a> ser::SerializeTupleStruct for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+    fn end(self) -> Result<()> {
+        self.end_list()
+    }
+}
+impl<'a> ser::SerializeTupleVariant for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+    fn end(self) -> Result<()> {
+        self.end_list()
+    }
+}
+impl<'a> ser::SerializeMap for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.element(key)
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut *self.ser)
+    }
+    fn end(self) -> Result<()> {
+        self.end_map()
+    }
+}
+impl<'a> ser::SerializeStruct for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.element(key)?;
+        value.serialize(&mut *self.ser)
+    }
+    fn end(self) -> Result<()> {
+        self.end_map()
+    }
+}
+impl<'a> ser::SerializeStructVariant for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.element(key)?;
+        value.serialize(&mut *self.ser)
+    }
+    fn end(self) -> Result<()> {
+        self.end_map()
+    }
+}
@@ -1,8 +1,11 @@
+use crate::errors::ActorDiscordError;
 use bitflags::__impl_bitflags;
 use serde::{
-    de::{Deserialize, Deserializer},
+    de::{self, Deserialize, Deserializer, Visitor},
     ser::{Serialize, Serializer},
 };
+use std::fmt;
+use std::str::FromStr;
 
 /// [Gateway Intents] will limit the events your bot will receive via the gateway.
 /// By default, all intents except [Privileged Intents] are selected.
@@ -18,8 +21,8 @@ use serde::{
 ///
 /// # Privileged Intents
 ///
-/// The intents [`GatewayIntents::GUILD_PRESENCES`] and [`GatewayIntents::GUILD_MEMBERS`]
-/// are [Privileged Intents]. They need to be enabled in the
+/// The intents [`GatewayIntents::GUILD_PRESENCES`], [`GatewayIntents::GUILD_MEMBERS`]
+/// and [`GatewayIntents::MESSAGE_CONTENT`] are [Privileged Intents]. They need to be enabled in the
 /// *developer portal*.
 ///
 /// **Note**:
@@ -83,9 +86,10 @@ __impl_bitflags! {
         /// - GUILD_BAN_ADD
         /// - GUILD_BAN_REMOVE
         GUILD_BANS = 1 << 2;
-        /// Enables following gateway event:
+        /// Enables following gateway events:
         ///
         /// - GUILD_EMOJIS_UPDATE
+        /// - GUILD_STICKERS_UPDATE
         GUILD_EMOJIS = 1 << 3;
         /// Enables following gateway event:
         ///
@@ -154,18 +158,287 @@ __impl_bitflags! {
         ///
         /// - TYPING_START
         DIRECT_MESSAGE_TYPING = 1 << 14;
+        /// Fills in `content`, `embeds`, `attachments` and `components` of messages.
+        /// Without it they arrive empty, except in DMs and messages that mention the bot.
+        ///
+        /// **Info**:
+        /// This intent is *privileged*.
+        /// In order to use it, you must head to your application in the
+        /// Developer Portal and enable the toggle for *Privileged Intents*.
+        MESSAGE_CONTENT = 1 << 15;
+        /// Enables following gateway events:
+        ///
+        /// - GUILD_SCHEDULED_EVENT_CREATE
+        /// - GUILD_SCHEDULED_EVENT_UPDATE
+        /// - GUILD_SCHEDULED_EVENT_DELETE
+        /// - GUILD_SCHEDULED_EVENT_USER_ADD
+        /// - GUILD_SCHEDULED_EVENT_USER_REMOVE
+        GUILD_SCHEDULED_EVENTS = 1 << 16;
+        /// Enables following gateway events:
+        ///
+        /// - AUTO_MODERATION_RULE_CREATE
+        /// - AUTO_MODERATION_RULE_UPDATE
+        /// - AUTO_MODERATION_RULE_DELETE
+        AUTO_MODERATION_CONFIGURATION = 1 << 20;
+        /// Enables following gateway event:
+        ///
+        /// - AUTO_MODERATION_ACTION_EXECUTION
+        AUTO_MODERATION_EXECUTION = 1 << 21;
+        /// Enables following gateway events:
+        ///
+        /// - MESSAGE_POLL_VOTE_ADD
+        /// - MESSAGE_POLL_VOTE_REMOVE
+        GUILD_MESSAGE_POLLS = 1 << 24;
+        /// Enables following gateway events:
+        ///
+        /// - MESSAGE_POLL_VOTE_ADD
+        /// - MESSAGE_POLL_VOTE_REMOVE
+        DIRECT_MESSAGE_POLLS = 1 << 25;
     }
 }
 
+/// flag names, as used by [GatewayIntents]'s `FromStr` and `Display`
+const INTENT_NAMES: &[(&str, GatewayIntents)] = &[
+    ("GUILDS", GatewayIntents::GUILDS),
+    ("GUILD_MEMBERS", GatewayIntents::GUILD_MEMBERS),
+    ("GUILD_BANS", GatewayIntents::GUILD_BANS),
+    ("GUILD_EMOJIS", GatewayIntents::GUILD_EMOJIS),
+    ("GUILD_INTEGRATIONS", GatewayIntents::GUILD_INTEGRATIONS),
+    ("GUILD_WEBHOOKS", GatewayIntents::GUILD_WEBHOOKS),
+    ("GUILD_INVITES", GatewayIntents::GUILD_INVITES),
+    ("GUILD_VOICE_STATES", GatewayIntents::GUILD_VOICE_STATES),
+    ("GUILD_PRESENCES", GatewayIntents::GUILD_PRESENCES),
+    ("GUILD_MESSAGES", GatewayIntents::GUILD_MESSAGES),
+    (
+        "GUILD_MESSAGE_REACTIONS",
+        GatewayIntents::GUILD_MESSAGE_REACTIONS,
+    ),
+    ("GUILD_MESSAGE_TYPING", GatewayIntents::GUILD_MESSAGE_TYPING),
+    ("DIRECT_MESSAGES", GatewayIntents::DIRECT_MESSAGES),
+    (
+        "DIRECT_MESSAGE_REACTIONS",
+        GatewayIntents::DIRECT_MESSAGE_REACTIONS,
+    ),
+    (
+        "DIRECT_MESSAGE_TYPING",
+        GatewayIntents::DIRECT_MESSAGE_TYPING,
+    ),
+    ("MESSAGE_CONTENT", GatewayIntents::MESSAGE_CONTENT),
+    (
+        "GUILD_SCHEDULED_EVENTS",
+        GatewayIntents::GUILD_SCHEDULED_EVENTS,
+    ),
+    (
+        "AUTO_MODERATION_CONFIGURATION",
+        GatewayIntents::AUTO_MODERATION_CONFIGURATION,
+    ),
+    (
+        "AUTO_MODERATION_EXECUTION",
+        GatewayIntents::AUTO_MODERATION_EXECUTION,
+    ),
+    ("GUILD_MESSAGE_POLLS", GatewayIntents::GUILD_MESSAGE_POLLS),
+    ("DIRECT_MESSAGE_POLLS", GatewayIntents::DIRECT_MESSAGE_POLLS),
+];
+
+/// the dispatch events each intent enables. events missing from here are sent whatever the intents
+const INTENT_EVENTS: &[(GatewayIntents, &[&str])] = &[
+    (
+        GatewayIntents::GUILDS,
+        &[
+            "GUILD_CREATE",
+            "GUILD_UPDATE",
+            "GUILD_DELETE",
+            "GUILD_ROLE_CREATE",
+            "GUILD_ROLE_UPDATE",
+            "GUILD_ROLE_DELETE",
+            "CHANNEL_CREATE",
+            "CHANNEL_UPDATE",
+            "CHANNEL_DELETE",
+            "CHANNEL_PINS_UPDATE",
+            "THREAD_CREATE",
+            "THREAD_UPDATE",
+            "THREAD_DELETE",
+            "THREAD_LIST_SYNC",
+            "THREAD_MEMBER_UPDATE",
+            "THREAD_MEMBERS_UPDATE",
+            "STAGE_INSTANCE_CREATE",
+            "STAGE_INSTANCE_UPDATE",
+            "STAGE_INSTANCE_DELETE",
+        ],
+    ),
+    (
+        GatewayIntents::GUILD_MEMBERS,
+        &[
+            "GUILD_MEMBER_ADD",
+            "GUILD_MEMBER_UPDATE",
+            "GUILD_MEMBER_REMOVE",
+            "THREAD_MEMBERS_UPDATE",
+        ],
+    ),
+    (
+        GatewayIntents::GUILD_BANS,
+        &["GUILD_BAN_ADD", "GUILD_BAN_REMOVE"],
+    ),
+    (
+        GatewayIntents::GUILD_EMOJIS,
+        &["GUILD_EMOJIS_UPDATE", "GUILD_STICKERS_UPDATE"],
+    ),
+    (
+        GatewayIntents::GUILD_INTEGRATIONS,
+        &[
+            "GUILD_INTEGRATIONS_UPDATE",
+            "INTEGRATION_CREATE",
+            "INTEGRATION_UPDATE",
+            "INTEGRATION_DELETE",
+        ],
+    ),
+    (GatewayIntents::GUILD_WEBHOOKS, &["WEBHOOKS_UPDATE"]),
+    (
+        GatewayIntents::GUILD_INVITES,
+        &["INVITE_CREATE", "INVITE_DELETE"],
+    ),
+    (GatewayIntents::GUILD_VOICE_STATES, &["VOICE_STATE_UPDATE"]),
+    (GatewayIntents::GUILD_PRESENCES, &["PRESENCE_UPDATE"]),
+    (
+        GatewayIntents::GUILD_MESSAGES,
+        &[
+            "MESSAGE_CREATE",
+            "MESSAGE_UPDATE",
+            "MESSAGE_DELETE",
+            "MESSAGE_DELETE_BULK",
+        ],
+    ),
+    (
+        GatewayIntents::GUILD_MESSAGE_REACTIONS,
+        &[
+            "MESSAGE_REACTION_ADD",
+            "MESSAGE_REACTION_REMOVE",
+            "MESSAGE_REACTION_REMOVE_ALL",
+            "MESSAGE_REACTION_REMOVE_EMOJI",
+        ],
+    ),
+    (GatewayIntents::GUILD_MESSAGE_TYPING, &["TYPING_START"]),
+    (
+        GatewayIntents::DIRECT_MESSAGES,
+        &[
+            "MESSAGE_CREATE",
+            "MESSAGE_UPDATE",
+            "MESSAGE_DELETE",
+            "CHANNEL_PINS_UPDATE",
+        ],
+    ),
+    (
+        GatewayIntents::DIRECT_MESSAGE_REACTIONS,
+        &[
+            "MESSAGE_REACTION_ADD",
+            "MESSAGE_REACTION_REMOVE",
+            "MESSAGE_REACTION_REMOVE_ALL",
+            "MESSAGE_REACTION_REMOVE_EMOJI",
+        ],
+    ),
+    (GatewayIntents::DIRECT_MESSAGE_TYPING, &["TYPING_START"]),
+    (
+        GatewayIntents::GUILD_SCHEDULED_EVENTS,
+        &[
+            "GUILD_SCHEDULED_EVENT_CREATE",
+            "GUILD_SCHEDULED_EVENT_UPDATE",
+            "GUILD_SCHEDULED_EVENT_DELETE",
+            "GUILD_SCHEDULED_EVENT_USER_ADD",
+            "GUILD_SCHEDULED_EVENT_USER_REMOVE",
+        ],
+    ),
+    (
+        GatewayIntents::AUTO_MODERATION_CONFIGURATION,
+        &[
+            "AUTO_MODERATION_RULE_CREATE",
+            "AUTO_MODERATION_RULE_UPDATE",
+            "AUTO_MODERATION_RULE_DELETE",
+        ],
+    ),
+    (
+        GatewayIntents::AUTO_MODERATION_EXECUTION,
+        &["AUTO_MODERATION_ACTION_EXECUTION"],
+    ),
+    (
+        GatewayIntents::GUILD_MESSAGE_POLLS,
+        &["MESSAGE_POLL_VOTE_ADD", "MESSAGE_POLL_VOTE_REMOVE"],
+    ),
+    (
+        GatewayIntents::DIRECT_MESSAGE_POLLS,
+        &["MESSAGE_POLL_VOTE_ADD", "MESSAGE_POLL_VOTE_REMOVE"],
+    ),
+];
+
 impl Default for GatewayIntents {
     fn default() -> Self {
         Self::empty()
     }
 }
 
+/// flag names separated by `|` or `,`, a number of raw bits, or one of the presets
+/// `all`, `non_privileged`, `privileged` and `none`. names are case insensitive
+impl FromStr for GatewayIntents {
+    type Err = ActorDiscordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut intents = GatewayIntents::empty();
+        for name in s.split(['|', ',']).map(str::trim) {
+            let name = name.to_ascii_uppercase();
+            intents |= match name.as_str() {
+                "" | "NONE" => GatewayIntents::empty(),
+                "ALL" => GatewayIntents::all(),
+                "NON_PRIVILEGED" => GatewayIntents::non_privileged(),
+                "PRIVILEGED" => GatewayIntents::privileged(),
+                _ => match INTENT_NAMES.iter().find(|(n, _)| *n == name) {
+                    Some((_, intent)) => *intent,
+                    None => match name.parse::<u64>() {
+                        Ok(bits) => GatewayIntents::from_bits_truncate(bits),
+                        Err(_) => return Err(ActorDiscordError::UnknownIntent(name)),
+                    },
+                },
+            }
+        }
+        Ok(intents)
+    }
+}
+
+/// the flag names joined with `|`, or `none`
+impl fmt::Display for GatewayIntents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = INTENT_NAMES
+            .iter()
+            .filter(|(_, intent)| self.contains(*intent))
+            .map(|(name, _)| *name)
+            .collect();
+        if names.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&names.join("|"))
+        }
+    }
+}
+
+/// accepts the raw bits, or names as parsed by `FromStr`
 impl<'de> Deserialize<'de> for GatewayIntents {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        Ok(Self::from_bits_truncate(u64::deserialize(deserializer)?))
+        struct IntentsVisitor;
+        impl<'de> Visitor<'de> for IntentsVisitor {
+            type Value = GatewayIntents;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("intent bits or names")
+            }
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(GatewayIntents::from_bits_truncate(v))
+            }
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(GatewayIntents::from_bits_truncate(v as u64))
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+        deserializer.deserialize_any(IntentsVisitor)
     }
 }
 
@@ -178,7 +451,6 @@ impl Serialize for GatewayIntents {
     }
 }
 
-#[cfg(feature = "model")]
 impl GatewayIntents {
     /// Gets all of the intents that don't are considered privileged by Discord.
     pub const fn non_privileged() -> GatewayIntents {
@@ -192,15 +464,75 @@ impl GatewayIntents {
     pub const fn privileged() -> GatewayIntents {
         // bitflags don't support const evaluation. Workaround.
         // See: https://github.com/bitflags/bitflags/issues/180
-        Self::from_bits_truncate(Self::GUILD_MEMBERS.bits() | Self::GUILD_PRESENCES.bits())
+        Self::from_bits_truncate(
+            Self::GUILD_MEMBERS.bits()
+                | Self::GUILD_PRESENCES.bits()
+                | Self::MESSAGE_CONTENT.bits(),
+        )
     }
 
     /// Checks if any of the included intents are privileged
     ///
     /// [GUILD_MEMBERS]: #associatedconstant.GUILD_MEMBERS
     /// [GUILD_PRESENCES]: #associatedconstant.GUILD_PRESENCES
+    /// [MESSAGE_CONTENT]: #associatedconstant.MESSAGE_CONTENT
     pub fn is_privileged(self) -> bool {
-        self.guild_members() || self.guild_presences()
+        self.intersects(Self::privileged())
+    }
+
+    /// the dispatch events these intents enable
+    pub fn events(self) -> Vec<&'static str> {
+        let mut events: Vec<&'static str> = vec![];
+        for (intent, intent_events) in INTENT_EVENTS {
+            if self.contains(*intent) {
+                for event in intent_events.iter() {
+                    if !events.contains(event) {
+                        events.push(event)
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    /// the intents that enable an event, any one of them is enough.
+    /// `None` if discord sends the event whatever the intents
+    pub fn for_event(event: &str) -> Option<GatewayIntents> {
+        INTENT_EVENTS
+            .iter()
+            .filter(|(_, events)| events.contains(&event))
+            .map(|(intent, _)| *intent)
+            .reduce(|a, b| a | b)
+    }
+
+    /// the events out of `events` these intents won't deliver, with a warning logged for each.
+    /// also warns when messages are expected without [Self::MESSAGE_CONTENT].
+    /// only checks the events it is given, see [crate::GatewayConnection::check_events]
+    pub fn check_events<'a>(self, events: &[&'a str]) -> Vec<&'a str> {
+        let missing: Vec<&'a str> = events
+            .iter()
+            .filter(|event| match Self::for_event(event) {
+                Some(intents) => !self.intersects(intents),
+                None => false,
+            })
+            .copied()
+            .collect();
+        for event in &missing {
+            log::warn!(
+                "{} won't be delivered, it needs one of the intents {:?}",
+                event,
+                Self::for_event(event).unwrap_or_default()
+            );
+        }
+        let messages = ["MESSAGE_CREATE", "MESSAGE_UPDATE"];
+        if !self.message_content()
+            && events
+                .iter()
+                .any(|event| messages.contains(event) && !missing.contains(event))
+        {
+            log::warn!("Without the MESSAGE_CONTENT intent most messages arrive without content");
+        }
+        missing
     }
 
     /// Shorthand for checking that the set of intents contains the
@@ -314,4 +646,112 @@ impl GatewayIntents {
     pub fn direct_message_typing(self) -> bool {
         self.contains(Self::DIRECT_MESSAGE_TYPING)
     }
+
+    /// Shorthand for checking that the set of intents contains the
+    /// [MESSAGE_CONTENT] intent.
+    ///
+    /// [MESSAGE_CONTENT]: Self::MESSAGE_CONTENT
+    pub fn message_content(self) -> bool {
+        self.contains(Self::MESSAGE_CONTENT)
+    }
+
+    /// Shorthand for checking that the set of intents contains the
+    /// [GUILD_SCHEDULED_EVENTS] intent.
+    ///
+    /// [GUILD_SCHEDULED_EVENTS]: Self::GUILD_SCHEDULED_EVENTS
+    pub fn guild_scheduled_events(self) -> bool {
+        self.contains(Self::GUILD_SCHEDULED_EVENTS)
+    }
+
+    /// Shorthand for checking that the set of intents contains the
+    /// [AUTO_MODERATION_CONFIGURATION] intent.
+    ///
+    /// [AUTO_MODERATION_CONFIGURATION]: Self::AUTO_MODERATION_CONFIGURATION
+    pub fn auto_moderation_configuration(self) -> bool {
+        self.contains(Self::AUTO_MODERATION_CONFIGURATION)
+    }
+
+    /// Shorthand for checking that the set of intents contains the
+    /// [AUTO_MODERATION_EXECUTION] intent.
+    ///
+    /// [AUTO_MODERATION_EXECUTION]: Self::AUTO_MODERATION_EXECUTION
+    pub fn auto_moderation_execution(self) -> bool {
+        self.contains(Self::AUTO_MODERATION_EXECUTION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GatewayIntents;
+
+    #[test]
+    fn names() {
+        let intents: GatewayIntents = "GUILDS|GUILD_MESSAGES|MESSAGE_CONTENT".parse().unwrap();
+        assert_eq!(
+            intents,
+            GatewayIntents::GUILDS
+                | GatewayIntents::GUILD_MESSAGES
+                | GatewayIntents::MESSAGE_CONTENT
+        );
+        assert_eq!(intents.to_string(), "GUILDS|GUILD_MESSAGES|MESSAGE_CONTENT");
+        assert_eq!(
+            "guilds, direct_messages".parse::<GatewayIntents>().unwrap(),
+            GatewayIntents::GUILDS | GatewayIntents::DIRECT_MESSAGES
+        );
+        assert_eq!(
+            "non_privileged|GUILD_MEMBERS"
+                .parse::<GatewayIntents>()
+                .unwrap(),
+            GatewayIntents::non_privileged() | GatewayIntents::GUILD_MEMBERS
+        );
+        assert_eq!(
+            "all"
+                .parse::<GatewayIntents>()
+                .unwrap()
+                .to_string()
+                .parse::<GatewayIntents>()
+                .unwrap(),
+            GatewayIntents::all()
+        );
+        assert_eq!("513".parse::<GatewayIntents>().unwrap().bits(), 513);
+        assert_eq!(GatewayIntents::empty().to_string(), "none");
+        assert!("GUILDS|NOT_AN_INTENT".parse::<GatewayIntents>().is_err());
+
+        let bits: GatewayIntents = serde_json::from_str("513").unwrap();
+        let names: GatewayIntents = serde_json::from_str(r#""GUILDS|GUILD_MESSAGES""#).unwrap();
+        assert_eq!(bits, names);
+        assert_eq!(serde_json::to_string(&names).unwrap(), "513");
+    }
+
+    #[test]
+    fn privileged() {
+        assert!(GatewayIntents::MESSAGE_CONTENT.is_privileged());
+        assert!(!GatewayIntents::non_privileged().contains(GatewayIntents::MESSAGE_CONTENT));
+        assert!(
+            GatewayIntents::non_privileged().contains(GatewayIntents::AUTO_MODERATION_EXECUTION)
+        );
+        assert!(!(GatewayIntents::GUILDS | GatewayIntents::GUILD_MESSAGES).is_privileged());
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(
+            GatewayIntents::for_event("MESSAGE_CREATE"),
+            Some(GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES)
+        );
+        assert_eq!(GatewayIntents::for_event("READY"), None);
+        assert!(GatewayIntents::GUILD_SCHEDULED_EVENTS
+            .events()
+            .contains(&"GUILD_SCHEDULED_EVENT_USER_ADD"));
+        let intents = GatewayIntents::GUILDS | GatewayIntents::DIRECT_MESSAGES;
+        assert_eq!(
+            intents.check_events(&[
+                "READY",
+                "MESSAGE_CREATE",
+                "GUILD_MEMBER_ADD",
+                "GUILD_CREATE"
+            ]),
+            vec!["GUILD_MEMBER_ADD"]
+        );
+    }
 }
@@ -1,14 +1,27 @@
 use actix_broker::SystemBroker;
 
 mod api;
+mod backoff;
+pub mod bot;
+mod compression;
+mod config;
 mod connection;
 pub mod discord;
 mod errors;
+#[cfg(feature = "etf")]
+pub mod etf;
 mod intents;
+mod members;
+mod ratelimit;
+mod shard;
 pub mod types;
 pub use api::DiscordAPI;
-pub use connection::DiscordBot;
+pub use bot::DiscordBot;
+pub use config::DiscordConfig;
+pub use connection::{GatewayConnection, GatewayHandle, ShutdownMode};
+pub use errors::{ActorDiscordError, DiscordApiError, FieldError, Result};
 pub use intents::GatewayIntents;
+pub use shard::{IdentifyLimiter, ShardManager};
 /// VERSION number of package
 pub const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 /// NAME of package
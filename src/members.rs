@@ -0,0 +1,135 @@
+use crate::types::events::{GuildMembers, GuildMembersChunk, SnowflakeID};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+#[derive(Debug)]
+struct Pending {
+    members: GuildMembers,
+    /// the request went out on the current connection
+    sent: bool,
+    reply: oneshot::Sender<GuildMembers>,
+}
+
+/// Request Guild Members calls waiting for their GUILD_MEMBERS_CHUNKs, by nonce.
+/// shared between a [crate::GatewayConnection] and its handles
+#[derive(Debug, Default)]
+pub struct MemberRequests {
+    next_nonce: AtomicU64,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+impl MemberRequests {
+    /// returns the nonce to send the request with, and where the members will arrive
+    pub fn register(&self, guild_id: SnowflakeID) -> (String, oneshot::Receiver<GuildMembers>) {
+        let nonce = format!(
+            "members-{}",
+            self.next_nonce.fetch_add(1, Ordering::Relaxed)
+        );
+        let (reply, receiver) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap();
+        // callers that stopped waiting leave their request behind
+        pending.retain(|_, pending| !pending.reply.is_closed());
+        let members = GuildMembers {
+            guild_id,
+            members: vec![],
+            not_found: vec![],
+            presences: vec![],
+        };
+        pending.insert(
+            nonce.clone(),
+            Pending {
+                members,
+                sent: false,
+                reply,
+            },
+        );
+        (nonce, receiver)
+    }
+
+    /// forget a request, its chunks are ignored if they arrive
+    pub fn cancel(&self, nonce: &str) {
+        self.pending.lock().unwrap().remove(nonce);
+    }
+
+    pub fn sent(&self, nonce: &str) {
+        if let Some(pending) = self.pending.lock().unwrap().get_mut(nonce) {
+            pending.sent = true;
+        }
+    }
+
+    /// add a chunk to its request, completing it on the last chunk.
+    /// gives the chunk back if no request is waiting for it
+    pub fn chunk(&self, chunk: GuildMembersChunk) -> Option<GuildMembersChunk> {
+        let mut pending = self.pending.lock().unwrap();
+        let nonce = match &chunk.nonce {
+            Some(nonce) if pending.contains_key(nonce) => nonce.clone(),
+            _ => return Some(chunk),
+        };
+        let request = pending.get_mut(&nonce).unwrap();
+        let last = chunk.chunk_index + 1 >= chunk.chunk_count;
+        request.members.members.extend(chunk.members);
+        request.members.not_found.extend(chunk.not_found);
+        request.members.presences.extend(chunk.presences);
+        if last {
+            let request = pending.remove(&nonce).unwrap();
+            // the caller may have given up waiting
+            let _ = request.reply.send(request.members);
+        }
+        None
+    }
+
+    /// the connection closed. chunks for requests sent on it will never arrive, so those fail
+    pub fn connection_lost(&self) {
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|_, pending| !pending.sent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemberRequests;
+    use crate::types::events::GuildMembersChunk;
+
+    fn chunk(nonce: &str, index: u64, count: u64, user: &str) -> GuildMembersChunk {
+        serde_json::from_str(&format!(
+            r#"{{"guild_id":"1","members":[{{"user":{{"id":"{}","username":"u","discriminator":"0001"}},"roles":[]}}],"chunk_index":{},"chunk_count":{},"nonce":"{}"}}"#,
+            user, index, count, nonce
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn assemble_chunks() {
+        let requests = MemberRequests::default();
+        let (nonce, mut reply) = requests.register(1.into());
+        requests.sent(&nonce);
+        assert!(requests.chunk(chunk(&nonce, 0, 2, "2")).is_none());
+        assert!(reply.try_recv().is_err());
+        assert!(requests.chunk(chunk("someone-else", 0, 1, "4")).is_some());
+        assert!(requests.chunk(chunk(&nonce, 1, 2, "3")).is_none());
+        let members = reply.await.unwrap();
+        assert_eq!(members.members.len(), 2);
+        assert_eq!(members.members[1].user.as_ref().unwrap().id.id, 3);
+
+        let (queued, mut queued_reply) = requests.register(1.into());
+        let (nonce, reply) = requests.register(1.into());
+        requests.sent(&nonce);
+        requests.connection_lost();
+        assert!(reply.await.is_err());
+        // not sent yet, so it goes out on the next connection
+        assert!(requests.chunk(chunk(&queued, 0, 1, "2")).is_none());
+        assert!(queued_reply.try_recv().is_ok());
+
+        let (cancelled, _reply) = requests.register(1.into());
+        requests.cancel(&cancelled);
+        assert!(requests.chunk(chunk(&cancelled, 0, 1, "2")).is_some());
+        // a dropped reply is cleared out by the next request
+        let (dropped, reply) = requests.register(1.into());
+        drop(reply);
+        requests.register(1.into());
+        assert!(requests.chunk(chunk(&dropped, 0, 1, "2")).is_some());
+    }
+}
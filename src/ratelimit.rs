@@ -0,0 +1,314 @@
+use awc::http::header::HeaderMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+use tokio::time::{Duration, Instant};
+
+const HEADER_BUCKET: &str = "x-ratelimit-bucket";
+const HEADER_LIMIT: &str = "x-ratelimit-limit";
+const HEADER_REMAINING: &str = "x-ratelimit-remaining";
+const HEADER_RESET_AFTER: &str = "x-ratelimit-reset-after";
+const HEADER_GLOBAL: &str = "x-ratelimit-global";
+
+/// the gateway closes connections that send more than 120 payloads a minute
+const GATEWAY_SEND_LIMIT: usize = 120;
+const GATEWAY_SEND_WINDOW: Duration = Duration::from_secs(60);
+/// sends kept back for heartbeats and IDENTIFY/RESUME, so commands can't starve them
+const GATEWAY_RESERVED_SENDS: usize = 5;
+
+/// path segments whose following ID is a 'major parameter'. Discord keeps separate buckets for each
+/// major parameter value, even if the route shares the same bucket hash
+const MAJOR_PARAMETERS: [&str; 3] = ["channels", "guilds", "webhooks"];
+
+/// A route is a HTTP method + path, with the IDs stripped out. The major parameter is kept aside
+/// as it forms part of the bucket
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Route {
+    pub key: String,
+    pub major: String,
+}
+impl Route {
+    pub fn new(method: &str, path: &str) -> Route {
+        let mut major = String::new();
+        let mut previous = "";
+        let mut segments: Vec<&str> = Vec::new();
+        for segment in path.trim_matches('/').split('/') {
+            if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) {
+                if major.is_empty() && MAJOR_PARAMETERS.contains(&previous) {
+                    major = segment.into();
+                    segments.push(":major");
+                } else {
+                    segments.push(":id");
+                }
+            } else {
+                segments.push(segment);
+            }
+            previous = segment;
+        }
+        Route {
+            key: format!("{} /{}", method, segments.join("/")),
+            major,
+        }
+    }
+}
+
+/// The rate limit headers returned by discord on every API response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimitHeaders {
+    pub bucket: Option<String>,
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    pub reset_after: Option<f64>,
+    pub global: bool,
+}
+impl RateLimitHeaders {
+    pub fn from_headers(headers: &HeaderMap) -> RateLimitHeaders {
+        let value = |name: &str| -> Option<&str> { headers.get(name)?.to_str().ok() };
+        RateLimitHeaders {
+            bucket: value(HEADER_BUCKET).map(String::from),
+            limit: value(HEADER_LIMIT).and_then(|v| v.parse().ok()),
+            remaining: value(HEADER_REMAINING).and_then(|v| v.parse().ok()),
+            reset_after: value(HEADER_RESET_AFTER).and_then(|v| v.parse().ok()),
+            global: value(HEADER_GLOBAL)
+                .map(|v| v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    limit: u64,
+    remaining: u64,
+    reset_at: Instant,
+    /// assumed length of the next window, until a response says otherwise
+    window: Duration,
+}
+
+/// Tracks discord's per-route buckets (and the global limit), so requests wait *before* they are
+/// sent instead of after receiving a 429.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// route key -> bucket hash supplied by discord
+    routes: Mutex<HashMap<String, String>>,
+    /// bucket hash + major parameter -> bucket state
+    buckets: Mutex<HashMap<String, Bucket>>,
+    global_reset: Mutex<Option<Instant>>,
+    /// route keys discord returned no bucket for
+    unlimited: Mutex<HashSet<String>>,
+    /// route key + major parameter -> lock held by the request finding out the bucket
+    discovering: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+impl RateLimiter {
+    fn bucket_key(&self, route: &Route) -> Option<String> {
+        let routes = self.routes.lock().unwrap();
+        routes
+            .get(&route.key)
+            .map(|hash| format!("{}:{}", hash, route.major))
+    }
+
+    /// the bucket for the route has been seen, or discord said it has none
+    fn known(&self, route: &Route) -> bool {
+        if self.unlimited.lock().unwrap().contains(&route.key) {
+            return true;
+        }
+        match self.bucket_key(route) {
+            Some(key) => self.buckets.lock().unwrap().contains_key(&key),
+            None => false,
+        }
+    }
+
+    /// how long we need to wait before the request can go. Reserves a slot in the bucket if one is available
+    fn reserve(&self, route: &Route) -> Option<Duration> {
+        let now = Instant::now();
+        if let Some(global_reset) = *self.global_reset.lock().unwrap() {
+            if global_reset > now {
+                return Some(global_reset - now);
+            }
+        }
+        let key = self.bucket_key(route)?;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_mut(&key)?;
+        if bucket.reset_at <= now {
+            bucket.remaining = bucket.limit;
+            bucket.reset_at = now + bucket.window;
+        }
+        if bucket.remaining > 0 {
+            bucket.remaining -= 1;
+            None
+        } else {
+            Some(bucket.reset_at - now)
+        }
+    }
+
+    /// wait until the route (and the global limit) allow another request.
+    /// until its bucket is known a route is treated as having a limit of 1: the returned guard must be
+    /// held until the response's headers have been passed to [RateLimiter::update]
+    pub async fn acquire(&self, route: &Route) -> Option<OwnedMutexGuard<()>> {
+        let guard = if self.known(route) {
+            None
+        } else {
+            let lock = self
+                .discovering
+                .lock()
+                .unwrap()
+                .entry(format!("{}:{}", route.key, route.major))
+                .or_default()
+                .clone();
+            let guard = lock.lock_owned().await;
+            // the request ahead of this one may have found the bucket
+            if self.known(route) {
+                None
+            } else {
+                Some(guard)
+            }
+        };
+        while let Some(delay) = self.reserve(route) {
+            log::debug!("Rate limited on {} waiting {:?}", route.key, delay);
+            tokio::time::sleep(delay).await;
+        }
+        guard
+    }
+
+    /// record the bucket state discord returned for the route
+    pub fn update(&self, route: &Route, headers: &RateLimitHeaders) {
+        if headers.bucket.is_none() {
+            self.unlimited.lock().unwrap().insert(route.key.clone());
+        }
+        if let Some(hash) = &headers.bucket {
+            self.unlimited.lock().unwrap().remove(&route.key);
+            self.routes
+                .lock()
+                .unwrap()
+                .insert(route.key.clone(), hash.clone());
+            if let (Some(remaining), Some(reset_after)) = (headers.remaining, headers.reset_after) {
+                let window = Duration::from_secs_f64(reset_after);
+                let bucket = Bucket {
+                    limit: headers.limit.unwrap_or(remaining).max(1),
+                    remaining,
+                    reset_at: Instant::now() + window,
+                    window,
+                };
+                self.buckets
+                    .lock()
+                    .unwrap()
+                    .insert(format!("{}:{}", hash, route.major), bucket);
+            }
+        }
+    }
+
+    /// a global 429 blocks every route until it expires
+    pub fn set_global(&self, retry_after: f64) {
+        let reset = Instant::now() + Duration::from_secs_f64(retry_after);
+        let mut global_reset = self.global_reset.lock().unwrap();
+        if global_reset.map(|r| r < reset).unwrap_or(true) {
+            *global_reset = Some(reset);
+        }
+    }
+}
+
+/// Counts payloads sent on a gateway connection over a sliding window
+#[derive(Debug)]
+pub struct SendLimiter {
+    limit: usize,
+    window: Duration,
+    sent: VecDeque<Instant>,
+}
+impl Default for SendLimiter {
+    fn default() -> Self {
+        SendLimiter::new(GATEWAY_SEND_LIMIT, GATEWAY_SEND_WINDOW)
+    }
+}
+impl SendLimiter {
+    pub fn new(limit: usize, window: Duration) -> SendLimiter {
+        SendLimiter {
+            limit,
+            window,
+            sent: VecDeque::with_capacity(limit),
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while matches!(self.sent.front(), Some(sent) if *sent + self.window <= now) {
+            self.sent.pop_front();
+        }
+    }
+
+    /// count a payload that was sent
+    pub fn record(&mut self) {
+        let now = Instant::now();
+        self.expire(now);
+        self.sent.push_back(now);
+    }
+
+    /// how long until a command can be sent. None if it can go now
+    pub fn command_delay(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        self.expire(now);
+        let allowed = self.limit.saturating_sub(GATEWAY_RESERVED_SENDS).max(1);
+        if self.sent.len() < allowed {
+            return None;
+        }
+        // wait for enough of the oldest sends to leave the window
+        let oldest = self.sent[self.sent.len() - allowed];
+        Some(oldest + self.window - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimitHeaders, RateLimiter, Route, SendLimiter};
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn route() {
+        let route = Route::new("GET", "/api/v9/channels/1234/messages/5678");
+        assert_eq!(route.key, "GET /api/v9/channels/:major/messages/:id");
+        assert_eq!(route.major, "1234");
+        let route = Route::new("PATCH", "/api/v9/users/1234");
+        assert_eq!(route.key, "PATCH /api/v9/users/:id");
+        assert_eq!(route.major, "");
+    }
+
+    #[tokio::test]
+    async fn waits_for_reset() {
+        let limiter = RateLimiter::default();
+        let route = Route::new("GET", "/api/v9/channels/1");
+        limiter.acquire(&route).await;
+        limiter.update(
+            &route,
+            &RateLimitHeaders {
+                bucket: Some("abc".into()),
+                limit: Some(1),
+                remaining: Some(0),
+                reset_after: Some(0.2),
+                global: false,
+            },
+        );
+        let start = Instant::now();
+        limiter.acquire(&route).await;
+        assert!(start.elapsed() >= Duration::from_millis(190));
+
+        // a different major parameter has its own bucket
+        let start = Instant::now();
+        limiter
+            .acquire(&Route::new("GET", "/api/v9/channels/2"))
+            .await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn gateway_send_limit() {
+        let mut limiter = SendLimiter::new(8, Duration::from_millis(200));
+        for _ in 0..3 {
+            assert!(limiter.command_delay().is_none());
+            limiter.record();
+        }
+        // 5 of the 8 are kept for heartbeats
+        let delay = limiter.command_delay().unwrap();
+        assert!(delay > Duration::from_millis(150));
+        limiter.record();
+        tokio::time::sleep(delay).await;
+        assert!(limiter.command_delay().is_none());
+    }
+}
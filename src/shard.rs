@@ -0,0 +1,255 @@
+use crate::errors::Result;
+use crate::types::gateway::{GatewayReply, Shard};
+use crate::{DiscordAPI, DiscordBot, DiscordConfig, GatewayConnection, GatewayIntents};
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+use url::Url;
+
+const GATEWAY_BOT: &str = "gateway/bot";
+/// discord allows `max_concurrency` IDENTIFYs every 5 seconds
+const IDENTIFY_WINDOW: Duration = Duration::from_secs(5);
+
+/// Spaces out IDENTIFY requests across shards.
+/// shards are grouped into `shard_id % max_concurrency` buckets, each bucket can identify once per window
+#[derive(Debug)]
+pub struct IdentifyLimiter {
+    max_concurrency: u64,
+    window: Duration,
+    next_identify: Mutex<HashMap<u64, Instant>>,
+}
+impl IdentifyLimiter {
+    pub fn new(max_concurrency: u64) -> IdentifyLimiter {
+        IdentifyLimiter {
+            max_concurrency: max_concurrency.max(1),
+            window: IDENTIFY_WINDOW,
+            next_identify: Default::default(),
+        }
+    }
+    /// wait until the shard is allowed to IDENTIFY
+    pub async fn wait(&self, shard_id: u64) {
+        let bucket = shard_id % self.max_concurrency;
+        loop {
+            let delay = {
+                let mut next_identify = self.next_identify.lock().unwrap();
+                let now = Instant::now();
+                match next_identify.get(&bucket) {
+                    Some(next) if *next > now => Some(*next - now),
+                    _ => {
+                        next_identify.insert(bucket, now + self.window);
+                        None
+                    }
+                }
+            };
+            match delay {
+                Some(delay) => {
+                    log::debug!("Shard {} waiting {:?} to identify", shard_id, delay);
+                    tokio::time::sleep(delay).await
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+/// Runs a [GatewayConnection] for each shard.
+pub struct ShardManager {
+    pub api: Arc<DiscordAPI>,
+    pub intents: GatewayIntents,
+    pub gateway: GatewayReply,
+    pub shard_count: u64,
+    pub identify_limiter: Arc<IdentifyLimiter>,
+}
+impl ShardManager {
+    /// create a manager using the shard count discord recommends
+    pub async fn create(api: Arc<DiscordAPI>, intents: GatewayIntents) -> Result<ShardManager> {
+        let gateway = api.get::<GatewayReply>(GATEWAY_BOT).await?;
+        let shard_count = gateway.shards.unwrap_or(1);
+        Ok(ShardManager::from_gateway(
+            api,
+            intents,
+            gateway,
+            shard_count,
+        ))
+    }
+
+    /// create a manager for the configured shard count, or the recommended one
+    pub async fn from_config(api: Arc<DiscordAPI>, config: &DiscordConfig) -> Result<ShardManager> {
+        match config.shard_count {
+            Some(shard_count) => {
+                ShardManager::with_shard_count(api, config.intents, shard_count).await
+            }
+            None => ShardManager::create(api, config.intents).await,
+        }
+    }
+
+    /// create a manager with a fixed number of shards
+    pub async fn with_shard_count(
+        api: Arc<DiscordAPI>,
+        intents: GatewayIntents,
+        shard_count: u64,
+    ) -> Result<ShardManager> {
+        let gateway = api.get::<GatewayReply>(GATEWAY_BOT).await?;
+        Ok(ShardManager::from_gateway(
+            api,
+            intents,
+            gateway,
+            shard_count,
+        ))
+    }
+
+    fn from_gateway(
+        api: Arc<DiscordAPI>,
+        intents: GatewayIntents,
+        gateway: GatewayReply,
+        shard_count: u64,
+    ) -> ShardManager {
+        let shard_count = shard_count.max(1);
+        let max_concurrency = match &gateway.session_start_limit {
+            Some(limit) => {
+                log::info!(
+                    "Shards {} sessions remaining {}/{} max concurrency {}",
+                    shard_count,
+                    limit.remaining,
+                    limit.total,
+                    limit.max_concurrency
+                );
+                if limit.remaining < shard_count {
+                    log::warn!(
+                        "Only {} session starts remaining, resets in {}ms",
+                        limit.remaining,
+                        limit.reset_after
+                    );
+                }
+                limit.max_concurrency
+            }
+            None => 1,
+        };
+        ShardManager {
+            api,
+            intents,
+            gateway,
+            shard_count,
+            identify_limiter: Arc::new(IdentifyLimiter::new(max_concurrency)),
+        }
+    }
+
+    /// a connection for each shard, sharing the identify limiter
+    pub fn connections(&self) -> Result<Vec<GatewayConnection>> {
+        let web_socket = Url::from_str(&self.gateway.url)?;
+        Ok((0..self.shard_count)
+            .map(|id| {
+                GatewayConnection::new(
+                    self.api.clone(),
+                    self.intents,
+                    web_socket.clone(),
+                    Shard {
+                        id,
+                        count: self.shard_count,
+                    },
+                    Some(self.identify_limiter.clone()),
+                )
+            })
+            .collect())
+    }
+
+    /// a [DiscordBot] actor for each shard, to start with [crate::bot::Start]
+    pub fn bots(&self) -> Result<Vec<DiscordBot>> {
+        Ok(self
+            .connections()?
+            .into_iter()
+            .map(DiscordBot::new)
+            .collect())
+    }
+
+    /// run every shard until each has stopped. a fatal error only stops its own shard,
+    /// the result of each shard is returned in shard order
+    pub async fn start(&self) -> Result<Vec<(Shard, Result<()>)>> {
+        let connections = self.connections()?;
+        Ok(
+            join_shards(connections.into_iter().map(|mut connection| async move {
+                let result = connection.start_websocket().await;
+                if let Err(e) = &result {
+                    log::error!("Shard {} stopped {}", connection.shard, e);
+                }
+                (connection.shard, result)
+            }))
+            .await,
+        )
+    }
+}
+
+async fn join_shards<F>(shards: impl IntoIterator<Item = F>) -> Vec<(Shard, Result<()>)>
+where
+    F: Future<Output = (Shard, Result<()>)>,
+{
+    futures::future::join_all(shards).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{join_shards, IdentifyLimiter};
+    use crate::errors::ActorDiscordError;
+    use crate::types::gateway::{GatewayCloseCode, GatewayReply, Shard};
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn gateway_bot() {
+        let reply: GatewayReply = serde_json::from_str(
+            r#"{"url":"wss://gateway.discord.gg","shards":9,"session_start_limit":{"total":1000,"remaining":999,"reset_after":14400000,"max_concurrency":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(reply.shards, Some(9));
+        assert_eq!(reply.session_start_limit.unwrap().max_concurrency, 1);
+    }
+
+    #[tokio::test]
+    async fn identify_concurrency() {
+        let limiter = IdentifyLimiter {
+            window: Duration::from_millis(200),
+            ..IdentifyLimiter::new(2)
+        };
+        let start = Instant::now();
+        limiter.wait(0).await;
+        limiter.wait(1).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        // shard 2 shares a bucket with shard 0
+        limiter.wait(2).await;
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[tokio::test]
+    async fn fatal_close_stops_one_shard() {
+        let shard = |id| Shard { id, count: 2 };
+        let results = join_shards(vec![
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                (shard(0), Ok(()))
+            }) as std::pin::Pin<Box<dyn std::future::Future<Output = _>>>,
+            Box::pin(async move {
+                (
+                    shard(1),
+                    Err(ActorDiscordError::GatewayClosed {
+                        code: GatewayCloseCode::DisallowedIntents,
+                        reason: "Disallowed intent(s).".into(),
+                    }),
+                )
+            }),
+        ])
+        .await;
+        // shard 0 kept running after shard 1 failed
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, shard(0));
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].0, shard(1));
+        assert!(matches!(
+            results[1].1,
+            Err(ActorDiscordError::GatewayClosed {
+                code: GatewayCloseCode::DisallowedIntents,
+                ..
+            })
+        ));
+    }
+}
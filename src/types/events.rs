@@ -1,27 +1,269 @@
+use crate::types::gateway::{GatewayCloseCode, GatewaySession, Shard};
+use actix_broker::BrokerMsg;
+use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_repr::*;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// an enum discord sends as a number. values added after this crate was written end up in
+/// `Unknown`, rather than failing the whole event
+macro_rules! number_enum {
+    (pub enum $name:ident { $($variant:ident = $value:literal,)* }) => {
+        #[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+        #[serde(from = "u8", into = "u8")]
+        pub enum $name {
+            $($variant,)*
+            Unknown(u8),
+        }
+        impl From<u8> for $name {
+            fn from(value: u8) -> Self {
+                match value {
+                    $($value => $name::$variant,)*
+                    value => $name::Unknown(value),
+                }
+            }
+        }
+        impl From<$name> for u8 {
+            fn from(value: $name) -> u8 {
+                match value {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(value) => value,
+                }
+            }
+        }
+    };
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
 //#[rtype(result = "Result<usize, ()>")]
 #[rtype(result = "()")]
-
 pub enum Event {
-    INIT,
-    GuildCreate(GuildCreate),
+    INIT(Shard),
+    Ready(Shard, Ready),
+    GuildCreate(Shard, GuildCreate),
 }
 #[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
 #[rtype(result = "()")]
 pub enum MessageEvent {
-    MessageCreate(MessageObject),
-    MessageUpdate(MessageObject),
-    MessageDelete(MessageObject),
+    MessageCreate(Shard, MessageObject),
+    MessageUpdate(Shard, MessageObject),
+    MessageDelete(Shard, MessageDelete),
+    MessageDeleteBulk(Shard, MessageDeleteBulk),
 }
 #[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
 #[rtype(result = "()")]
 pub enum ChannelEvent {
-    ChannelCreate(GuildChannel),
-    ChannelUpdate(GuildChannel),
-    ChannelDelete(GuildChannel),
+    ChannelCreate(Shard, GuildChannel),
+    ChannelUpdate(Shard, GuildChannel),
+    ChannelDelete(Shard, GuildChannel),
+    ChannelPinsUpdate(Shard, ChannelPinsUpdate),
+    WebhooksUpdate(Shard, WebhooksUpdate),
+}
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub enum GuildEvent {
+    GuildUpdate(Shard, Guild),
+    GuildDelete(Shard, UnavailableGuild),
+    GuildRoleCreate(Shard, GuildRole),
+    GuildRoleUpdate(Shard, GuildRole),
+    GuildRoleDelete(Shard, GuildRoleDelete),
+    GuildBanAdd(Shard, GuildBan),
+    GuildBanRemove(Shard, GuildBan),
+    GuildEmojisUpdate(Shard, GuildEmojisUpdate),
+    GuildIntegrationsUpdate(Shard, GuildIntegrationsUpdate),
+}
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub enum MemberEvent {
+    GuildMemberAdd(Shard, GuildMemberEvent),
+    GuildMemberUpdate(Shard, GuildMemberEvent),
+    GuildMemberRemove(Shard, GuildMemberRemove),
+    /// chunks that don't belong to a `GatewayHandle::request_guild_members` call
+    GuildMembersChunk(Shard, GuildMembersChunk),
+}
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub enum ReactionEvent {
+    MessageReactionAdd(Shard, MessageReaction),
+    MessageReactionRemove(Shard, MessageReaction),
+    MessageReactionRemoveAll(Shard, MessageReactionRemoveAll),
+    MessageReactionRemoveEmoji(Shard, MessageReactionRemoveEmoji),
+}
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub enum PresenceEvent {
+    PresenceUpdate(Shard, PresenceUpdate),
+    TypingStart(Shard, TypingStart),
+}
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub enum ThreadEvent {
+    ThreadCreate(Shard, ThreadChannel),
+    ThreadUpdate(Shard, ThreadChannel),
+    ThreadDelete(Shard, ThreadChannel),
+    ThreadListSync(Shard, ThreadListSync),
+    ThreadMemberUpdate(Shard, ThreadMember),
+    ThreadMembersUpdate(Shard, ThreadMembersUpdate),
+}
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub enum InviteEvent {
+    InviteCreate(Shard, InviteCreate),
+    InviteDelete(Shard, InviteDelete),
+}
+
+// the dispatches published in each group, checked against the intents when subscribed to with
+// `SubscribeEvents`, or listed in `GatewayConnection::expected_events`
+impl Event {
+    pub const EVENTS: &'static [&'static str] = &["READY", "GUILD_CREATE"];
+}
+impl MessageEvent {
+    pub const EVENTS: &'static [&'static str] = &[
+        "MESSAGE_CREATE",
+        "MESSAGE_UPDATE",
+        "MESSAGE_DELETE",
+        "MESSAGE_DELETE_BULK",
+    ];
+}
+impl ChannelEvent {
+    pub const EVENTS: &'static [&'static str] = &[
+        "CHANNEL_CREATE",
+        "CHANNEL_UPDATE",
+        "CHANNEL_DELETE",
+        "CHANNEL_PINS_UPDATE",
+        "WEBHOOKS_UPDATE",
+    ];
+}
+impl GuildEvent {
+    pub const EVENTS: &'static [&'static str] = &[
+        "GUILD_UPDATE",
+        "GUILD_DELETE",
+        "GUILD_ROLE_CREATE",
+        "GUILD_ROLE_UPDATE",
+        "GUILD_ROLE_DELETE",
+        "GUILD_BAN_ADD",
+        "GUILD_BAN_REMOVE",
+        "GUILD_EMOJIS_UPDATE",
+        "GUILD_INTEGRATIONS_UPDATE",
+    ];
+}
+impl MemberEvent {
+    pub const EVENTS: &'static [&'static str] = &[
+        "GUILD_MEMBER_ADD",
+        "GUILD_MEMBER_UPDATE",
+        "GUILD_MEMBER_REMOVE",
+        "GUILD_MEMBERS_CHUNK",
+    ];
+}
+impl ReactionEvent {
+    pub const EVENTS: &'static [&'static str] = &[
+        "MESSAGE_REACTION_ADD",
+        "MESSAGE_REACTION_REMOVE",
+        "MESSAGE_REACTION_REMOVE_ALL",
+        "MESSAGE_REACTION_REMOVE_EMOJI",
+    ];
+}
+impl PresenceEvent {
+    pub const EVENTS: &'static [&'static str] = &["PRESENCE_UPDATE", "TYPING_START"];
+}
+impl ThreadEvent {
+    pub const EVENTS: &'static [&'static str] = &[
+        "THREAD_CREATE",
+        "THREAD_UPDATE",
+        "THREAD_DELETE",
+        "THREAD_LIST_SYNC",
+        "THREAD_MEMBER_UPDATE",
+        "THREAD_MEMBERS_UPDATE",
+    ];
+}
+impl InviteEvent {
+    pub const EVENTS: &'static [&'static str] = &["INVITE_CREATE", "INVITE_DELETE"];
+}
+
+/// a group of dispatches published on the broker, see [crate::bot::SubscribeEvents]
+pub trait EventGroup: BrokerMsg {
+    const EVENTS: &'static [&'static str];
+}
+macro_rules! event_group {
+    ($($group:ty),*) => {
+        $(
+            impl EventGroup for $group {
+                const EVENTS: &'static [&'static str] = <$group>::EVENTS;
+            }
+        )*
+    };
+}
+event_group!(
+    Event,
+    MessageEvent,
+    ChannelEvent,
+    GuildEvent,
+    MemberEvent,
+    ReactionEvent,
+    PresenceEvent,
+    ThreadEvent,
+    InviteEvent
+);
+
+/// round trip time between a heartbeat and its ACK. published after each ACK
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub struct GatewayLatency {
+    pub shard: Shard,
+    pub latest: Duration,
+    /// average over the last few heartbeats
+    pub average: Duration,
+}
+
+/// where a gateway connection is in its lifecycle
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ConnectionState {
+    /// not connected, waiting to reconnect
+    Disconnected,
+    /// opening the websocket
+    Connecting,
+    /// HELLO received, IDENTIFY sent
+    Identifying,
+    /// HELLO received, RESUME sent
+    Resuming,
+    /// READY or RESUMED received, dispatches are flowing
+    Ready,
+    /// stopped for good, the bot will not reconnect
+    Closed,
+}
+
+/// published on every [ConnectionState] transition
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub struct ConnectionStatus {
+    pub shard: Shard,
+    pub previous: ConnectionState,
+    pub state: ConnectionState,
+    /// the close frame that ended the connection, if the gateway sent one
+    pub code: Option<GatewayCloseCode>,
+    pub reason: Option<String>,
+    /// set once `Closed` if the session can still be resumed, e.g. after [crate::ShutdownMode::KeepSession]
+    pub session: Option<GatewaySession>,
+}
+
+/// which dispatches are also published untyped, as [RawEvent]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub enum RawEvents {
+    #[default]
+    Off,
+    /// only dispatches this crate has no type for
+    Unknown,
+    All,
+}
+
+/// a gateway dispatch as it was received. opt in with `GatewayConnection::raw_events`
+#[derive(Debug, Clone, Deserialize, Serialize, actix::Message)]
+#[rtype(result = "()")]
+pub struct RawEvent {
+    pub name: String,
+    pub shard: Shard,
+    pub sequence: Option<usize>,
+    pub payload: serde_json::Value,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -36,21 +278,41 @@ impl Serialize for SnowflakeID {
         serializer.serialize_str(&self.id.to_string())
     }
 }
-impl<'de> Deserialize<'de> for SnowflakeID {
-    fn deserialize<D>(deserializer: D) -> Result<SnowflakeID, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s: String = String::deserialize(deserializer)?;
+/// snowflakes are strings in JSON, but integers in ETF
+struct SnowflakeVisitor;
+impl<'de> Visitor<'de> for SnowflakeVisitor {
+    type Value = SnowflakeID;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a snowflake ID as a string or integer")
+    }
+    fn visit_u64<E: serde::de::Error>(self, id: u64) -> Result<SnowflakeID, E> {
+        Ok(SnowflakeID { id })
+    }
+    fn visit_i64<E: serde::de::Error>(self, id: i64) -> Result<SnowflakeID, E> {
+        match u64::try_from(id) {
+            Ok(id) => Ok(SnowflakeID { id }),
+            Err(e) => Err(E::custom(e)),
+        }
+    }
+    fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<SnowflakeID, E> {
         match s.parse::<u64>() {
             Err(_e) => {
                 eprintln!("u64 Fail {} {:#?}", s, _e);
-                Err(serde::de::Error::custom(_e))
+                Err(E::custom(_e))
             }
             Ok(val) => Ok(SnowflakeID { id: val }),
         }
     }
 }
+impl<'de> Deserialize<'de> for SnowflakeID {
+    fn deserialize<D>(deserializer: D) -> Result<SnowflakeID, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(SnowflakeVisitor)
+    }
+}
 impl From<u64> for SnowflakeID {
     fn from(id: u64) -> Self {
         SnowflakeID { id }
@@ -62,9 +324,9 @@ impl From<&str> for SnowflakeID {
         SnowflakeID { id: num }
     }
 }
-impl ToString for SnowflakeID {
-    fn to_string(&self) -> String {
-        format!("{}", self.id)
+impl std::fmt::Display for SnowflakeID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.id)
     }
 }
 
@@ -81,20 +343,22 @@ pub struct Guild {
     pub name: Option<String>,
     pub owner_id: SnowflakeID,
 }
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize_repr, Serialize_repr)]
-#[repr(u8)]
-pub enum ChannelType {
-    GuildText = 0,
-    DM = 1,
-    GuildVoice = 2,
-    GroupDm = 3,
-    GuildCategory = 4,
-    GuildNews = 5,
-    GuildStore = 6,
-    GuildNewsThread = 10,
-    GuildPublicThread = 11,
-    GuildPrivateThread = 12,
-    GuildStageVoice = 13,
+number_enum! {
+    pub enum ChannelType {
+        GuildText = 0,
+        DM = 1,
+        GuildVoice = 2,
+        GroupDm = 3,
+        GuildCategory = 4,
+        GuildNews = 5,
+        GuildStore = 6,
+        GuildNewsThread = 10,
+        GuildPublicThread = 11,
+        GuildPrivateThread = 12,
+        GuildStageVoice = 13,
+        GuildDirectory = 14,
+        GuildForum = 15,
+    }
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GuildChannel {
@@ -154,32 +418,32 @@ pub struct GuildHashes {
     pub version: usize,
 }
 
-#[derive(Debug, Clone, Deserialize_repr, Serialize_repr)]
-#[repr(u8)]
-pub enum MessageObjectType {
-    Default = 0,
-    RecipientAdd = 1,
-    RecipientRemove = 2,
-    Call = 3,
-    ChannelNameChange = 4,
-    ChannelIconChange = 5,
-    ChannelPinnedMessage = 6,
-    GuildMemberJoin = 7,
-    UserPremiumGuildSubscription = 8,
-    UserPremiumGuildSubscriptionTier1 = 9,
-    UserPremiumGuildSubscriptionTier2 = 10,
-    UserPremiumGuildSubscriptionTier3 = 11,
-    ChannelFollowAdd = 12,
-    GuildDiscoveryDisqualified = 14,
-    GuildDiscoveryRequalified = 15,
-    GuildDiscoveryGracePeriodInitialWarning = 16,
-    GuildDiscoveryGracePeriodFinalWarning = 17,
-    ThreadCreated = 18,
-    Reply = 19,
-    ChatInputCommand = 20,
-    ThreadStarterMessage = 21,
-    GuildInviteReminder = 22,
-    ContextMenuCommand = 23,
+number_enum! {
+    pub enum MessageObjectType {
+        Default = 0,
+        RecipientAdd = 1,
+        RecipientRemove = 2,
+        Call = 3,
+        ChannelNameChange = 4,
+        ChannelIconChange = 5,
+        ChannelPinnedMessage = 6,
+        GuildMemberJoin = 7,
+        UserPremiumGuildSubscription = 8,
+        UserPremiumGuildSubscriptionTier1 = 9,
+        UserPremiumGuildSubscriptionTier2 = 10,
+        UserPremiumGuildSubscriptionTier3 = 11,
+        ChannelFollowAdd = 12,
+        GuildDiscoveryDisqualified = 14,
+        GuildDiscoveryRequalified = 15,
+        GuildDiscoveryGracePeriodInitialWarning = 16,
+        GuildDiscoveryGracePeriodFinalWarning = 17,
+        ThreadCreated = 18,
+        Reply = 19,
+        ChatInputCommand = 20,
+        ThreadStarterMessage = 21,
+        GuildInviteReminder = 22,
+        ContextMenuCommand = 23,
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -218,6 +482,324 @@ pub struct UserObject {
     pub public_flags: Option<u64>,
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageDelete {
+    pub id: SnowflakeID,
+    pub channel_id: SnowflakeID,
+    pub guild_id: Option<SnowflakeID>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageDeleteBulk {
+    pub ids: Vec<SnowflakeID>,
+    pub channel_id: SnowflakeID,
+    pub guild_id: Option<SnowflakeID>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Emoji {
+    /// None for unicode emoji
+    pub id: Option<SnowflakeID>,
+    pub name: Option<String>,
+    pub animated: Option<bool>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageReaction {
+    pub user_id: SnowflakeID,
+    pub channel_id: SnowflakeID,
+    pub message_id: SnowflakeID,
+    pub guild_id: Option<SnowflakeID>,
+    /// only on MESSAGE_REACTION_ADD in guilds
+    pub member: Option<GuildMember>,
+    pub emoji: Emoji,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageReactionRemoveAll {
+    pub channel_id: SnowflakeID,
+    pub message_id: SnowflakeID,
+    pub guild_id: Option<SnowflakeID>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageReactionRemoveEmoji {
+    pub channel_id: SnowflakeID,
+    pub message_id: SnowflakeID,
+    pub guild_id: Option<SnowflakeID>,
+    pub emoji: Emoji,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChannelPinsUpdate {
+    pub guild_id: Option<SnowflakeID>,
+    pub channel_id: SnowflakeID,
+    pub last_pin_timestamp: Option<String>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebhooksUpdate {
+    pub guild_id: SnowflakeID,
+    pub channel_id: SnowflakeID,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PartialApplication {
+    pub id: SnowflakeID,
+    pub flags: Option<u64>,
+}
+/// the READY dispatch, sent once IDENTIFY succeeds
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Ready {
+    /// gateway version
+    pub v: u64,
+    /// the bot's own user
+    pub user: UserObject,
+    /// every guild starts unavailable, and is sent later as GUILD_CREATE
+    pub guilds: Vec<UnavailableGuild>,
+    pub session_id: String,
+    pub resume_gateway_url: Option<String>,
+    /// only set when sharding was requested in IDENTIFY
+    pub shard: Option<Shard>,
+    pub application: PartialApplication,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnavailableGuild {
+    pub id: SnowflakeID,
+    /// not set if the bot was removed from the guild
+    pub unavailable: Option<bool>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoleObject {
+    pub id: SnowflakeID,
+    pub name: String,
+    pub color: u64,
+    pub hoist: bool,
+    pub icon: Option<String>,
+    pub unicode_emoji: Option<String>,
+    pub position: i64,
+    pub permissions: String,
+    pub managed: bool,
+    pub mentionable: bool,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildRole {
+    pub guild_id: SnowflakeID,
+    pub role: RoleObject,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildRoleDelete {
+    pub guild_id: SnowflakeID,
+    pub role_id: SnowflakeID,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildBan {
+    pub guild_id: SnowflakeID,
+    pub user: UserObject,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildEmojisUpdate {
+    pub guild_id: SnowflakeID,
+    pub emojis: Vec<Emoji>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildIntegrationsUpdate {
+    pub guild_id: SnowflakeID,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildMember {
+    /// not included in MESSAGE_CREATE and MESSAGE_REACTION_ADD
+    pub user: Option<UserObject>,
+    pub nick: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<SnowflakeID>,
+    pub joined_at: Option<String>,
+    pub premium_since: Option<String>,
+    pub deaf: Option<bool>,
+    pub mute: Option<bool>,
+    pub pending: Option<bool>,
+    pub communication_disabled_until: Option<String>,
+}
+/// GUILD_MEMBER_ADD and GUILD_MEMBER_UPDATE
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildMemberEvent {
+    pub guild_id: SnowflakeID,
+    #[serde(flatten)]
+    pub member: GuildMember,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildMemberRemove {
+    pub guild_id: SnowflakeID,
+    pub user: UserObject,
+}
+/// a reply to Request Guild Members (op 8)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildMembersChunk {
+    pub guild_id: SnowflakeID,
+    pub members: Vec<GuildMember>,
+    pub chunk_index: u64,
+    pub chunk_count: u64,
+    /// requested user ids that aren't members
+    #[serde(default)]
+    pub not_found: Vec<SnowflakeID>,
+    #[serde(default)]
+    pub presences: Vec<PresenceUpdate>,
+    pub nonce: Option<String>,
+}
+/// every chunk of a Request Guild Members reply, put together
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GuildMembers {
+    pub guild_id: SnowflakeID,
+    pub members: Vec<GuildMember>,
+    pub not_found: Vec<SnowflakeID>,
+    pub presences: Vec<PresenceUpdate>,
+}
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Online,
+    Idle,
+    Dnd,
+    Invisible,
+    Offline,
+}
+number_enum! {
+    pub enum ActivityType {
+        Game = 0,
+        Streaming = 1,
+        Listening = 2,
+        Watching = 3,
+        Custom = 4,
+        Competing = 5,
+    }
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Activity {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub u_type: ActivityType,
+    /// only for `Streaming`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// the text of a `Custom` status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+impl Activity {
+    pub fn new(u_type: ActivityType, name: &str) -> Self {
+        Activity {
+            name: name.into(),
+            u_type,
+            url: None,
+            state: None,
+            details: None,
+        }
+    }
+    pub fn playing(name: &str) -> Self {
+        Activity::new(ActivityType::Game, name)
+    }
+    pub fn watching(name: &str) -> Self {
+        Activity::new(ActivityType::Watching, name)
+    }
+    /// a custom status, shown as the text
+    pub fn custom(text: &str) -> Self {
+        Activity {
+            state: Some(text.into()),
+            ..Activity::new(ActivityType::Custom, "Custom Status")
+        }
+    }
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClientStatus {
+    pub desktop: Option<Status>,
+    pub mobile: Option<Status>,
+    pub web: Option<Status>,
+}
+/// presence updates only guarantee the user's id
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PartialUser {
+    pub id: SnowflakeID,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PresenceUpdate {
+    pub user: PartialUser,
+    pub guild_id: Option<SnowflakeID>,
+    pub status: Status,
+    #[serde(default)]
+    pub activities: Vec<Activity>,
+    pub client_status: Option<ClientStatus>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TypingStart {
+    pub channel_id: SnowflakeID,
+    pub guild_id: Option<SnowflakeID>,
+    pub user_id: SnowflakeID,
+    /// unix time in seconds
+    pub timestamp: u64,
+    pub member: Option<Box<GuildMember>>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThreadMetadata {
+    pub archived: bool,
+    pub auto_archive_duration: u64,
+    pub archive_timestamp: String,
+    pub locked: Option<bool>,
+}
+/// THREAD_DELETE only includes the id, guild_id, parent_id and type
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThreadChannel {
+    pub id: SnowflakeID,
+    #[serde(rename = "type")]
+    pub u_type: ChannelType,
+    pub guild_id: Option<SnowflakeID>,
+    pub parent_id: Option<SnowflakeID>,
+    pub owner_id: Option<SnowflakeID>,
+    pub name: Option<String>,
+    pub last_message_id: Option<SnowflakeID>,
+    pub message_count: Option<u64>,
+    pub member_count: Option<u64>,
+    pub thread_metadata: Option<ThreadMetadata>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThreadMember {
+    /// the thread's id. omitted in GUILD_CREATE
+    pub id: Option<SnowflakeID>,
+    pub user_id: Option<SnowflakeID>,
+    /// only on THREAD_MEMBER_UPDATE
+    pub guild_id: Option<SnowflakeID>,
+    pub join_timestamp: String,
+    pub flags: u64,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThreadListSync {
+    pub guild_id: SnowflakeID,
+    /// the channels being synced. None means the whole guild
+    pub channel_ids: Option<Vec<SnowflakeID>>,
+    pub threads: Vec<ThreadChannel>,
+    pub members: Vec<ThreadMember>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThreadMembersUpdate {
+    pub id: SnowflakeID,
+    pub guild_id: SnowflakeID,
+    pub member_count: u64,
+    #[serde(default)]
+    pub added_members: Vec<ThreadMember>,
+    #[serde(default)]
+    pub removed_member_ids: Vec<SnowflakeID>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InviteCreate {
+    pub channel_id: SnowflakeID,
+    pub code: String,
+    pub created_at: String,
+    pub guild_id: Option<SnowflakeID>,
+    pub inviter: Option<UserObject>,
+    pub max_age: u64,
+    pub max_uses: u64,
+    pub temporary: bool,
+    pub uses: u64,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InviteDelete {
+    pub channel_id: SnowflakeID,
+    pub guild_id: Option<SnowflakeID>,
+    pub code: String,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Embed {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -1,56 +1,483 @@
-use crate::{NAME, VERSION};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::types::events::{Activity, SnowflakeID, Status};
+use crate::NAME;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_repr::*;
+use std::convert::TryFrom;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct GatewayReply {
     pub url: String,
+    /// recommended number of shards. only returned by `gateway/bot`
+    pub shards: Option<u64>,
+    pub session_start_limit: Option<SessionStartLimit>,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionStartLimit {
+    pub total: u64,
+    pub remaining: u64,
+    /// milliseconds until the limit resets
+    pub reset_after: u64,
+    /// number of IDENTIFY requests allowed per 5 seconds
+    pub max_concurrency: u64,
+}
+
+/// the shard a connection is for. a bot that isn't sharded is shard 0 of 1.
+/// discord sends it as `[id, count]`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(from = "[u64; 2]", into = "[u64; 2]")]
+pub struct Shard {
+    pub id: u64,
+    pub count: u64,
+}
+impl Default for Shard {
+    fn default() -> Self {
+        Shard { id: 0, count: 1 }
+    }
+}
+impl From<[u64; 2]> for Shard {
+    fn from([id, count]: [u64; 2]) -> Self {
+        Shard { id, count }
+    }
+}
+impl From<Shard> for [u64; 2] {
+    fn from(shard: Shard) -> Self {
+        [shard.id, shard.count]
+    }
+}
+impl std::fmt::Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}, {}]", self.id, self.count)
+    }
+}
+
+/// what a client should do after the gateway closes the connection
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CloseAction {
+    /// reconnect and RESUME the session
+    Resume,
+    /// the session is gone, reconnect and IDENTIFY
+    Identify,
+    /// reconnecting won't help, the configuration needs fixing
+    Fatal,
+}
+
+/// close codes the gateway sends, see
+/// <https://discord.com/developers/docs/topics/opcodes-and-status-codes#gateway-gateway-close-event-codes>
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(from = "u16", into = "u16")]
+pub enum GatewayCloseCode {
+    Normal,
+    GoingAway,
+    UnknownError,
+    UnknownOpcode,
+    DecodeError,
+    NotAuthenticated,
+    AuthenticationFailed,
+    AlreadyAuthenticated,
+    /// no longer sent by discord
+    SessionNoLongerValid,
+    InvalidSeq,
+    RateLimited,
+    SessionTimedOut,
+    InvalidShard,
+    ShardingRequired,
+    InvalidApiVersion,
+    InvalidIntents,
+    DisallowedIntents,
+    Other(u16),
+}
+impl GatewayCloseCode {
+    pub fn action(&self) -> CloseAction {
+        match self {
+            GatewayCloseCode::NotAuthenticated
+            | GatewayCloseCode::SessionNoLongerValid
+            | GatewayCloseCode::InvalidSeq
+            | GatewayCloseCode::SessionTimedOut => CloseAction::Identify,
+            GatewayCloseCode::AuthenticationFailed
+            | GatewayCloseCode::InvalidShard
+            | GatewayCloseCode::ShardingRequired
+            | GatewayCloseCode::InvalidApiVersion
+            | GatewayCloseCode::InvalidIntents
+            | GatewayCloseCode::DisallowedIntents => CloseAction::Fatal,
+            _ => CloseAction::Resume,
+        }
+    }
+    pub fn is_fatal(&self) -> bool {
+        self.action() == CloseAction::Fatal
+    }
+    /// what the code means, and for fatal codes what to fix
+    pub fn description(&self) -> &'static str {
+        match self {
+            GatewayCloseCode::Normal => "normal closure",
+            GatewayCloseCode::GoingAway => "going away",
+            GatewayCloseCode::UnknownError => "unknown error",
+            GatewayCloseCode::UnknownOpcode => "sent an invalid opcode or payload",
+            GatewayCloseCode::DecodeError => "sent a payload that couldn't be decoded",
+            GatewayCloseCode::NotAuthenticated => "sent a payload before IDENTIFY",
+            GatewayCloseCode::AuthenticationFailed => "authentication failed, check the bot token",
+            GatewayCloseCode::AlreadyAuthenticated => "sent more than one IDENTIFY",
+            GatewayCloseCode::SessionNoLongerValid => "session no longer valid",
+            GatewayCloseCode::InvalidSeq => "invalid sequence number on RESUME",
+            GatewayCloseCode::RateLimited => "sending payloads too quickly",
+            GatewayCloseCode::SessionTimedOut => "session timed out",
+            GatewayCloseCode::InvalidShard => "invalid shard sent in IDENTIFY",
+            GatewayCloseCode::ShardingRequired => "too many guilds, the bot must be sharded",
+            GatewayCloseCode::InvalidApiVersion => "invalid gateway API version",
+            GatewayCloseCode::InvalidIntents => "invalid intents",
+            GatewayCloseCode::DisallowedIntents => {
+                "disallowed intents, enable the privileged intents in the developer portal"
+            }
+            GatewayCloseCode::Other(_) => "unrecognised close code",
+        }
+    }
+}
+impl From<u16> for GatewayCloseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => GatewayCloseCode::Normal,
+            1001 => GatewayCloseCode::GoingAway,
+            4000 => GatewayCloseCode::UnknownError,
+            4001 => GatewayCloseCode::UnknownOpcode,
+            4002 => GatewayCloseCode::DecodeError,
+            4003 => GatewayCloseCode::NotAuthenticated,
+            4004 => GatewayCloseCode::AuthenticationFailed,
+            4005 => GatewayCloseCode::AlreadyAuthenticated,
+            4006 => GatewayCloseCode::SessionNoLongerValid,
+            4007 => GatewayCloseCode::InvalidSeq,
+            4008 => GatewayCloseCode::RateLimited,
+            4009 => GatewayCloseCode::SessionTimedOut,
+            4010 => GatewayCloseCode::InvalidShard,
+            4011 => GatewayCloseCode::ShardingRequired,
+            4012 => GatewayCloseCode::InvalidApiVersion,
+            4013 => GatewayCloseCode::InvalidIntents,
+            4014 => GatewayCloseCode::DisallowedIntents,
+            code => GatewayCloseCode::Other(code),
+        }
+    }
+}
+impl From<GatewayCloseCode> for u16 {
+    fn from(code: GatewayCloseCode) -> Self {
+        match code {
+            GatewayCloseCode::Normal => 1000,
+            GatewayCloseCode::GoingAway => 1001,
+            GatewayCloseCode::UnknownError => 4000,
+            GatewayCloseCode::UnknownOpcode => 4001,
+            GatewayCloseCode::DecodeError => 4002,
+            GatewayCloseCode::NotAuthenticated => 4003,
+            GatewayCloseCode::AuthenticationFailed => 4004,
+            GatewayCloseCode::AlreadyAuthenticated => 4005,
+            GatewayCloseCode::SessionNoLongerValid => 4006,
+            GatewayCloseCode::InvalidSeq => 4007,
+            GatewayCloseCode::RateLimited => 4008,
+            GatewayCloseCode::SessionTimedOut => 4009,
+            GatewayCloseCode::InvalidShard => 4010,
+            GatewayCloseCode::ShardingRequired => 4011,
+            GatewayCloseCode::InvalidApiVersion => 4012,
+            GatewayCloseCode::InvalidIntents => 4013,
+            GatewayCloseCode::DisallowedIntents => 4014,
+            GatewayCloseCode::Other(code) => code,
+        }
+    }
+}
+impl std::fmt::Display for GatewayCloseCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", u16::from(*self), self.description())
+    }
 }
 
-pub const GATEWAY: usize = 0;
-pub const HEARTBEAT: usize = 1;
-pub const IDENTIFY: usize = 2;
-pub const RECONNECT: usize = 7;
-pub const INVALID_SESSION: usize = 9;
-pub const HELLO: usize = 10;
-pub const ACK: usize = 11;
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize_repr, Serialize_repr)]
+#[repr(u8)]
+pub enum Opcode {
+    Dispatch = 0,
+    Heartbeat = 1,
+    Identify = 2,
+    PresenceUpdate = 3,
+    VoiceStateUpdate = 4,
+    Resume = 6,
+    Reconnect = 7,
+    RequestGuildMembers = 8,
+    InvalidSession = 9,
+    Hello = 10,
+    HeartbeatAck = 11,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EventGuildCreate {
     pub id: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GatewayHello {
     pub heartbeat_interval: u64,
 }
-#[derive(Debug, Serialize, Deserialize)]
+/// the connection properties sent with IDENTIFY
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentifyProperties {
+    pub os: String,
+    /// discord asks for the library name in both browser and device
+    pub browser: String,
+    pub device: String,
+}
+impl Default for IdentifyProperties {
+    fn default() -> Self {
+        let library = NAME.unwrap_or("PFC-Discord");
+        IdentifyProperties {
+            os: std::env::consts::OS.into(),
+            browser: library.into(),
+            device: library.into(),
+        }
+    }
+}
+
+/// op 2, starts a new session. see [GatewayIdentify::builder]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GatewayIdentify {
     pub token: String,
     pub intents: u64,
-    pub properties: HashMap<String, String>,
-    pub v: usize,
+    pub properties: IdentifyProperties,
+    /// compress individual payloads. not used with the zlib-stream transport
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compress: Option<bool>,
+    /// guilds with more members than this are sent without their offline members
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_threshold: Option<u8>,
+    pub shard: Shard,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence: Option<UpdatePresence>,
 }
 impl GatewayIdentify {
-    pub fn create(token: &str, intents: u64) -> Self {
-        let mut p: HashMap<String, String> = Default::default();
-        p.insert("$os".into(), "linux".into());
-        p.insert("$browser".into(), NAME.unwrap_or("PFC-Discord").into());
-        p.insert("$device".into(), VERSION.unwrap_or("DEV").into());
+    pub fn create(token: &str, intents: u64, shard: Shard) -> Self {
+        GatewayIdentify::builder(token, intents)
+            .shard(shard)
+            .build()
+    }
 
-        GatewayIdentify {
-            token: String::from(token),
-            intents,
-            properties: p,
-            v: 9,
+    pub fn builder(token: &str, intents: u64) -> GatewayIdentifyBuilder {
+        GatewayIdentifyBuilder {
+            identify: GatewayIdentify {
+                token: String::from(token),
+                intents,
+                properties: Default::default(),
+                compress: None,
+                large_threshold: None,
+                shard: Default::default(),
+                presence: None,
+            },
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct GatewayMessage {
-    pub t: Option<String>,
-    pub s: Option<usize>,
-    pub op: usize,
-    pub d: serde_json::Value,
+/// sets the optional IDENTIFY fields
+#[derive(Debug, Clone)]
+pub struct GatewayIdentifyBuilder {
+    identify: GatewayIdentify,
+}
+impl GatewayIdentifyBuilder {
+    pub fn properties(mut self, properties: IdentifyProperties) -> Self {
+        self.identify.properties = properties;
+        self
+    }
+    pub fn os(mut self, os: &str) -> Self {
+        self.identify.properties.os = os.into();
+        self
+    }
+    pub fn browser(mut self, browser: &str) -> Self {
+        self.identify.properties.browser = browser.into();
+        self
+    }
+    pub fn device(mut self, device: &str) -> Self {
+        self.identify.properties.device = device.into();
+        self
+    }
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.identify.compress = Some(compress);
+        self
+    }
+    /// discord accepts 50 to 250, values outside that are clamped
+    pub fn large_threshold(mut self, large_threshold: u8) -> Self {
+        self.identify.large_threshold = Some(large_threshold.clamp(50, 250));
+        self
+    }
+    pub fn shard(mut self, shard: Shard) -> Self {
+        self.identify.shard = shard;
+        self
+    }
+    pub fn presence(mut self, presence: Option<UpdatePresence>) -> Self {
+        self.identify.presence = presence;
+        self
+    }
+    pub fn build(self) -> GatewayIdentify {
+        self.identify
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayResume {
+    pub token: String,
+    pub session_id: String,
+    pub seq: usize,
+}
+
+/// what a restarted process needs to RESUME a session, see [crate::ShutdownMode::KeepSession]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GatewaySession {
+    pub session_id: String,
+    pub sequence: usize,
+    pub resume_gateway_url: Option<String>,
+}
+
+/// op 3, sets the bot's status and activities
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdatePresence {
+    /// unix time in milliseconds the bot went idle
+    pub since: Option<u64>,
+    pub activities: Vec<Activity>,
+    pub status: Status,
+    pub afk: bool,
+}
+impl UpdatePresence {
+    pub fn new(status: Status) -> Self {
+        UpdatePresence {
+            since: None,
+            activities: vec![],
+            status,
+            afk: false,
+        }
+    }
+    pub fn activity(mut self, activity: Activity) -> Self {
+        self.activities.push(activity);
+        self
+    }
+}
+
+/// op 4, joins, moves between or leaves voice channels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateVoiceState {
+    pub guild_id: SnowflakeID,
+    /// None to disconnect
+    pub channel_id: Option<SnowflakeID>,
+    pub self_mute: bool,
+    pub self_deaf: bool,
+}
+
+/// op 8, the members arrive as GUILD_MEMBERS_CHUNK dispatches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestGuildMembers {
+    pub guild_id: SnowflakeID,
+    /// username prefix, empty for every member. can't be used with `user_ids`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// 0 for no limit
+    pub limit: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presences: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_ids: Option<Vec<SnowflakeID>>,
+    /// echoed back in the chunks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+/// payloads a client sends, encoded as `{"op": .., "d": ..}`
+#[derive(Debug, Clone)]
+pub enum GatewayCommand {
+    /// the last sequence number received
+    Heartbeat(Option<usize>),
+    Identify(GatewayIdentify),
+    PresenceUpdate(UpdatePresence),
+    VoiceStateUpdate(UpdateVoiceState),
+    Resume(GatewayResume),
+    RequestGuildMembers(RequestGuildMembers),
+    /// anything else. `op` isn't checked, so this can be used for opcodes this crate doesn't know
+    Raw {
+        op: u8,
+        d: serde_json::Value,
+    },
+}
+impl GatewayCommand {
+    pub fn op(&self) -> u8 {
+        let opcode = match self {
+            GatewayCommand::Heartbeat(_) => Opcode::Heartbeat,
+            GatewayCommand::Identify(_) => Opcode::Identify,
+            GatewayCommand::PresenceUpdate(_) => Opcode::PresenceUpdate,
+            GatewayCommand::VoiceStateUpdate(_) => Opcode::VoiceStateUpdate,
+            GatewayCommand::Resume(_) => Opcode::Resume,
+            GatewayCommand::RequestGuildMembers(_) => Opcode::RequestGuildMembers,
+            GatewayCommand::Raw { op, .. } => return *op,
+        };
+        opcode as u8
+    }
+}
+impl Serialize for GatewayCommand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut message = serializer.serialize_struct("GatewayCommand", 2)?;
+        message.serialize_field("op", &self.op())?;
+        match self {
+            GatewayCommand::Heartbeat(seq) => message.serialize_field("d", seq)?,
+            GatewayCommand::Identify(identify) => message.serialize_field("d", identify)?,
+            GatewayCommand::PresenceUpdate(presence) => message.serialize_field("d", presence)?,
+            GatewayCommand::VoiceStateUpdate(voice) => message.serialize_field("d", voice)?,
+            GatewayCommand::Resume(resume) => message.serialize_field("d", resume)?,
+            GatewayCommand::RequestGuildMembers(request) => {
+                message.serialize_field("d", request)?
+            }
+            GatewayCommand::Raw { d, .. } => message.serialize_field("d", d)?,
+        }
+        message.end()
+    }
+}
+
+/// payloads the gateway sends
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "GatewayPayload")]
+pub enum GatewayEvent {
+    /// `data` is typed later, based on the event name
+    Dispatch {
+        name: String,
+        sequence: Option<usize>,
+        data: serde_json::Value,
+    },
+    /// send a heartbeat straight away
+    Heartbeat,
+    Reconnect,
+    InvalidSession {
+        resumable: bool,
+    },
+    Hello(GatewayHello),
+    HeartbeatAck,
+}
+
+/// the envelope every received payload comes in
+#[derive(Debug, Deserialize)]
+struct GatewayPayload {
+    op: Opcode,
+    #[serde(default)]
+    d: serde_json::Value,
+    s: Option<usize>,
+    t: Option<String>,
+}
+impl TryFrom<GatewayPayload> for GatewayEvent {
+    type Error = serde_json::Error;
+
+    fn try_from(payload: GatewayPayload) -> Result<Self, Self::Error> {
+        use serde::de::Error;
+        Ok(match payload.op {
+            Opcode::Dispatch => GatewayEvent::Dispatch {
+                name: payload
+                    .t
+                    .ok_or_else(|| Error::custom("dispatch without an event name"))?,
+                sequence: payload.s,
+                data: payload.d,
+            },
+            Opcode::Heartbeat => GatewayEvent::Heartbeat,
+            Opcode::Reconnect => GatewayEvent::Reconnect,
+            Opcode::InvalidSession => GatewayEvent::InvalidSession {
+                resumable: payload.d.as_bool().unwrap_or(false),
+            },
+            Opcode::Hello => GatewayEvent::Hello(serde_json::from_value(payload.d)?),
+            Opcode::HeartbeatAck => GatewayEvent::HeartbeatAck,
+            op => return Err(Error::custom(format!("{:?} is only sent by clients", op))),
+        })
+    }
 }